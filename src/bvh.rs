@@ -0,0 +1,384 @@
+// Bounding volume hierarchy over axis-aligned boxes, split with the surface
+// area heuristic.
+
+use crate::shape::Hit;
+use crate::{Ray, Vec3};
+
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY),
+        }
+    }
+
+    // for shapes without bounds, like planes
+    pub fn infinite() -> Aabb {
+        Aabb {
+            min: Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY),
+            max: Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        }
+    }
+
+    pub fn from_points(ps: &[Vec3]) -> Aabb {
+        ps.iter().fold(Aabb::empty(), |b, &p| b.grow(p))
+    }
+
+    pub fn grow(&self, p: Vec3) -> Aabb {
+        Aabb {
+            min: Vec3::new(
+                self.min.x.min(p.x),
+                self.min.y.min(p.y),
+                self.min.z.min(p.z),
+            ),
+            max: Vec3::new(
+                self.max.x.max(p.x),
+                self.max.y.max(p.y),
+                self.max.z.max(p.z),
+            ),
+        }
+    }
+
+    pub fn union(&self, b: &Aabb) -> Aabb {
+        self.grow(b.min).grow(b.max)
+    }
+
+    // the box shared by both, empty if they are apart
+    pub fn overlap(&self, b: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::new(
+                self.min.x.max(b.min.x),
+                self.min.y.max(b.min.y),
+                self.min.z.max(b.min.z),
+            ),
+            max: Vec3::new(
+                self.max.x.min(b.max.x),
+                self.max.y.min(b.max.y),
+                self.max.z.min(b.max.z),
+            ),
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        [self.min, self.max]
+            .iter()
+            .all(|v| v.x.is_finite() && v.y.is_finite() && v.z.is_finite())
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    fn area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    // slab test; inv is 1/ray.d per component
+    fn hit(&self, o: Vec3, inv: Vec3, t_max: f64) -> bool {
+        let (x0, x1) = ((self.min.x - o.x) * inv.x, (self.max.x - o.x) * inv.x);
+        let (y0, y1) = ((self.min.y - o.y) * inv.y, (self.max.y - o.y) * inv.y);
+        let (z0, z1) = ((self.min.z - o.z) * inv.z, (self.max.z - o.z) * inv.z);
+        let t0 = max(max(min(x0, x1), min(y0, y1)), max(min(z0, z1), 0.0));
+        let t1 = min(min(max(x0, x1), max(y0, y1)), min(max(z0, z1), t_max));
+        t0 <= t1
+    }
+}
+
+// plain comparisons; f64::min and max spend time on NaN handling
+fn min(a: f64, b: f64) -> f64 {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+fn max(a: f64, b: f64) -> f64 {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+fn axis(v: Vec3, a: usize) -> f64 {
+    match a {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+const BINS: usize = 12;
+const MAX_LEAF: usize = 8;
+// keeps the traversal stack bounded
+const MAX_DEPTH: usize = 48;
+
+fn bin(c: f64, lo: f64, ext: f64) -> usize {
+    (((c - lo) / ext * BINS as f64) as usize).min(BINS - 1)
+}
+
+// Interior nodes have count 0; their children are the next node and right.
+struct Node {
+    b: Aabb,
+    start: usize,
+    count: usize,
+    right: usize,
+    axis: usize,
+}
+
+pub struct Bvh {
+    nodes: Vec<Node>,
+    items: Vec<usize>,
+}
+
+impl Bvh {
+    // Items are referred to by their index in bounds.
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            items: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            let cents: Vec<Vec3> = bounds.iter().map(|b| b.centroid()).collect();
+            let mut items = std::mem::take(&mut bvh.items);
+            bvh.build_node(&mut items, 0, 0, bounds, &cents);
+            bvh.items = items;
+        }
+        bvh
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn build_node(
+        &mut self,
+        items: &mut [usize],
+        start: usize,
+        depth: usize,
+        bounds: &[Aabb],
+        cents: &[Vec3],
+    ) {
+        let idx = self.nodes.len();
+        let b = items
+            .iter()
+            .fold(Aabb::empty(), |b, &i| b.union(&bounds[i]));
+        let cb = items.iter().fold(Aabb::empty(), |b, &i| b.grow(cents[i]));
+        let n = items.len();
+        self.nodes.push(Node {
+            b,
+            start,
+            count: n,
+            right: 0,
+            axis: 0,
+        });
+        if n <= 2 || depth >= MAX_DEPTH {
+            return;
+        }
+
+        // cheapest binned split: (cost, axis, bin)
+        let mut best: Option<(f64, usize, usize)> = None;
+        for a in 0..3 {
+            let lo = axis(cb.min, a);
+            let ext = axis(cb.max, a) - lo;
+            if ext <= 0.0 {
+                continue;
+            }
+            let mut cnt = [0usize; BINS];
+            let mut bb = [Aabb::empty(); BINS];
+            for &i in items.iter() {
+                let k = bin(axis(cents[i], a), lo, ext);
+                cnt[k] += 1;
+                bb[k] = bb[k].union(&bounds[i]);
+            }
+            let mut right_area = [0.0; BINS];
+            let mut acc = Aabb::empty();
+            for k in (1..BINS).rev() {
+                acc = acc.union(&bb[k]);
+                right_area[k] = acc.area();
+            }
+            let (mut lb, mut lc) = (Aabb::empty(), 0);
+            for k in 0..BINS - 1 {
+                lb = lb.union(&bb[k]);
+                lc += cnt[k];
+                let rc = n - lc;
+                if lc == 0 || rc == 0 {
+                    continue;
+                }
+                let cost = 1.0 + (lb.area() * lc as f64 + right_area[k + 1] * rc as f64) / b.area();
+                if best.is_none_or(|(c, _, _)| cost < c) {
+                    best = Some((cost, a, k));
+                }
+            }
+        }
+        let (a, k) = match best {
+            Some((cost, a, k)) if cost < n as f64 || n > MAX_LEAF => (a, k),
+            _ => return,
+        };
+
+        let lo = axis(cb.min, a);
+        let ext = axis(cb.max, a) - lo;
+        let mut mid = 0;
+        for j in 0..n {
+            if bin(axis(cents[items[j]], a), lo, ext) <= k {
+                items.swap(j, mid);
+                mid += 1;
+            }
+        }
+        let (left, right) = items.split_at_mut(mid);
+        self.build_node(left, start, depth + 1, bounds, cents);
+        let r = self.nodes.len();
+        self.build_node(right, start + mid, depth + 1, bounds, cents);
+        let node = &mut self.nodes[idx];
+        node.count = 0;
+        node.right = r;
+        node.axis = a;
+    }
+
+    // Closest hit; f(item, t_max) intersects a single item.
+    pub fn intersect(
+        &self,
+        ray: &Ray,
+        mut t_max: f64,
+        mut f: impl FnMut(usize, f64) -> Option<Hit>,
+    ) -> Option<Hit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv = Vec3::new(1.0 / ray.d.x, 1.0 / ray.d.y, 1.0 / ray.d.z);
+        let mut hit: Option<Hit> = None;
+        let mut stack = [0usize; MAX_DEPTH + 2];
+        let mut sp = 1;
+        while sp > 0 {
+            sp -= 1;
+            let idx = stack[sp];
+            let node = &self.nodes[idx];
+            if !node.b.hit(ray.o, inv, t_max) {
+                continue;
+            }
+            if node.count > 0 {
+                for &i in &self.items[node.start..node.start + node.count] {
+                    if let Some(h) = f(i, t_max) {
+                        t_max = h.t;
+                        hit = Some(h);
+                    }
+                }
+            } else {
+                // visit the nearer child first
+                let (near, far) = if axis(ray.d, node.axis) < 0.0 {
+                    (node.right, idx + 1)
+                } else {
+                    (idx + 1, node.right)
+                };
+                stack[sp] = far;
+                stack[sp + 1] = near;
+                sp += 2;
+            }
+        }
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Triangle;
+    use crate::scene::closest;
+    use crate::shape::{Shape, Sphere};
+    use crate::{random, seed_random, INF};
+
+    fn rand_vec(scale: f64) -> Vec3 {
+        Vec3::new(random() - 0.5, random() - 0.5, random() - 0.5) * scale
+    }
+
+    fn depth(bvh: &Bvh, idx: usize) -> usize {
+        let node = &bvh.nodes[idx];
+        if node.count > 0 {
+            return 0;
+        }
+        1 + depth(bvh, idx + 1).max(depth(bvh, node.right))
+    }
+
+    // The BVH finds the same closest hits as testing every shape, for random
+    // rays and for rays aimed at each shape. Returns the tree's depth.
+    fn check(shapes: &[Box<dyn Shape>]) -> usize {
+        let bounds: Vec<Aabb> = shapes.iter().map(|s| s.bounds()).collect();
+        let bvh = Bvh::build(&bounds);
+        let mut rays: Vec<Ray> = (0..500)
+            .map(|_| Ray::new(rand_vec(200.0), rand_vec(1.0).norm()))
+            .collect();
+        for b in &bounds {
+            let o = rand_vec(200.0);
+            rays.push(Ray::new(o, (b.centroid() - o).norm()));
+        }
+        for r in &rays {
+            let want = closest(shapes.iter().map(|s| &**s), r, INF);
+            let got = bvh.intersect(r, INF, |i, t| shapes[i].intersect(r, t));
+            match (want, got) {
+                (Some(a), Some(b)) => assert!((a.t - b.t).abs() < 1e-9, "{} {}", a.t, b.t),
+                (a, b) => assert_eq!(a.is_some(), b.is_some()),
+            }
+        }
+        if bvh.nodes.is_empty() {
+            0
+        } else {
+            depth(&bvh, 0)
+        }
+    }
+
+    #[test]
+    fn random_shapes() {
+        seed_random(1);
+        let mut shapes: Vec<Box<dyn Shape>> = Vec::new();
+        for i in 0..300 {
+            let p = rand_vec(100.0);
+            if i % 2 == 0 {
+                shapes.push(Box::new(Sphere::new(random() * 5.0, p, 0)));
+            } else {
+                let (b, c) = (p + rand_vec(10.0), p + rand_vec(10.0));
+                shapes.push(Box::new(Triangle::new(p, b, c, 0)));
+            }
+        }
+        check(&shapes);
+        check(&shapes[..1]);
+        check(&[]);
+    }
+
+    // no axis to split along
+    #[test]
+    fn one_bin() {
+        seed_random(2);
+        let shapes: Vec<Box<dyn Shape>> = (1..40)
+            .map(|i| Box::new(Sphere::new(i as f64, Vec3::zero(), 0)) as Box<dyn Shape>)
+            .collect();
+        assert_eq!(check(&shapes), 0);
+    }
+
+    // The triangles close in on the origin so fast that each split only
+    // takes off the farthest one, so the tree would be deeper than MAX_DEPTH
+    // if it weren't cut off there.
+    #[test]
+    fn too_deep() {
+        seed_random(3);
+        let shapes: Vec<Box<dyn Shape>> = (0..80)
+            .map(|k| {
+                let x = 50.0 / 16f64.powi(k);
+                let (a, b, c) = (
+                    Vec3::new(x, -x, -x),
+                    Vec3::new(x, x, 0.0),
+                    Vec3::new(x, -x, x),
+                );
+                Box::new(Triangle::new(a, b, c, 0)) as Box<dyn Shape>
+            })
+            .collect();
+        assert_eq!(check(&shapes), MAX_DEPTH);
+    }
+}
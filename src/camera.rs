@@ -0,0 +1,322 @@
+use crate::{random, Ray, Vec3};
+use std::f64::consts::PI;
+
+// vertical field of view of the original smallpt camera, 2*atan(0.5135/2)
+pub const SMALLPT_FOV: f64 = 28.799_316_231_093_748;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    // parallel rays; the view is as high as the perspective one at the focus
+    // distance
+    Orthographic,
+    // equidistant: angle from the view axis grows linearly with the distance
+    // from the image centre, reaching fov/2 at the top and bottom edges
+    Fisheye,
+    // 360 x 180 degree panorama, best at 2:1
+    Equirect,
+}
+
+impl Projection {
+    pub const NAMES: [(&'static str, Projection); 4] = [
+        ("perspective", Projection::Perspective),
+        ("ortho", Projection::Orthographic),
+        ("fisheye", Projection::Fisheye),
+        ("equirect", Projection::Equirect),
+    ];
+
+    pub fn from_name(s: &str) -> Option<Projection> {
+        Projection::NAMES
+            .iter()
+            .find(|(n, _)| *n == s)
+            .map(|&(_, p)| p)
+    }
+
+    pub fn name(self) -> &'static str {
+        Projection::NAMES
+            .iter()
+            .find(|(_, p)| *p == self)
+            .unwrap()
+            .0
+    }
+}
+
+// Screen basis from Camera::basis(). cx and cy span the whole image at unit
+// distance along the view axis d.
+pub struct Basis {
+    pub cx: Vec3,
+    pub cy: Vec3,
+    pub d: Vec3,
+}
+
+pub struct Camera {
+    pub o: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    // vertical field of view in degrees
+    pub fov: f64,
+    // primary rays start on the plane this far in front of o; smallpt used
+    // 140 to get past the back wall of the Cornell box. Perspective and
+    // orthographic only.
+    pub near: f64,
+    // thin lens radius, 0 for a pinhole
+    pub aperture: f64,
+    // distance of the plane in focus along the view axis, 0 to focus on target
+    pub focus: f64,
+    pub projection: Projection,
+}
+
+impl Camera {
+    // The camera every smallpt scene was written for, looking at the middle
+    // of the Cornell box.
+    pub fn smallpt() -> Camera {
+        let o = Vec3::new(50.0, 52.0, 295.6);
+        Camera {
+            near: 140.0,
+            ..Camera::look_at(
+                o,
+                // o + (0, -0.042612, -1) * 214
+                Vec3::new(50.0, 42.881032, 81.6),
+                Vec3::new(0.0, 1.0, 0.0),
+                SMALLPT_FOV,
+            )
+        }
+    }
+
+    pub fn look_at(o: Vec3, target: Vec3, up: Vec3, fov: f64) -> Camera {
+        Camera {
+            o,
+            target,
+            up,
+            fov,
+            near: 0.0,
+            aperture: 0.0,
+            focus: 0.0,
+            projection: Projection::Perspective,
+        }
+    }
+
+    pub fn focus_distance(&self) -> f64 {
+        if self.focus > 0.0 {
+            self.focus
+        } else {
+            let v = self.target - self.o;
+            v.dot(&v).sqrt()
+        }
+    }
+
+    pub fn basis(&self, w: usize, h: usize) -> Basis {
+        let d = (self.target - self.o).norm();
+        let mut right = d % self.up;
+        if right.dot(&right) < 1e-12 {
+            // looking straight along up
+            let alt = if d.x.abs() > 0.1 {
+                Vec3::new(0.0, 1.0, 0.0)
+            } else {
+                Vec3::new(1.0, 0.0, 0.0)
+            };
+            right = d % alt;
+        }
+        let right = right.norm();
+        let up = right % d;
+        let t = (self.fov.to_radians() * 0.5).tan();
+        let cx = right * (t * 2.0 * (w as f64) / (h as f64));
+        let cy = up * (t * 2.0);
+        Basis { cx, cy, d }
+    }
+
+    // Primary ray through screen position (sx, sy) in [-0.5, 0.5], or None
+    // outside the image circle of a fisheye.
+    pub fn ray(&self, b: &Basis, sx: f64, sy: f64) -> Option<Ray> {
+        let right = b.cx.norm();
+        let up = b.cy.norm();
+        let aspect = (b.cx.dot(&b.cx) / b.cy.dot(&b.cy)).sqrt();
+        let dir = match self.projection {
+            Projection::Perspective => return Some(self.perspective_ray(b, sx, sy)),
+            Projection::Orthographic => {
+                let s = self.focus_distance();
+                let o = self.o + b.cx * (sx * s) + b.cy * (sy * s);
+                return Some(Ray::new(o + b.d * self.near, b.d));
+            }
+            Projection::Fisheye => {
+                let (px, py) = (sx * aspect, sy);
+                let r = 2.0 * (px * px + py * py).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = r * self.fov.to_radians() * 0.5;
+                let side = if r > 0.0 {
+                    (right * px + up * py) * (2.0 / r)
+                } else {
+                    Vec3::zero()
+                };
+                b.d * theta.cos() + side * theta.sin()
+            }
+            Projection::Equirect => {
+                let phi = sx * 2.0 * PI;
+                let lat = sy * PI;
+                b.d * (lat.cos() * phi.cos()) + right * (lat.cos() * phi.sin()) + up * lat.sin()
+            }
+        };
+        Some(Ray::new(self.o, dir.norm()))
+    }
+
+    fn perspective_ray(&self, b: &Basis, sx: f64, sy: f64) -> Ray {
+        let d = b.cx * sx + b.cy * sy + b.d;
+        if self.aperture <= 0.0 {
+            return Ray::new(self.o + d * self.near, d.norm());
+        }
+        // d has unit length along the view axis, so this is on the focal plane
+        let p = self.o + d * self.focus_distance();
+        let r1 = 2.0 * PI * random();
+        let r2s = random().sqrt() * self.aperture;
+        let lens = self.o + b.cx.norm() * (r1.cos() * r2s) + b.cy.norm() * (r1.sin() * r2s);
+        let dir = (p - lens).norm();
+        Ray::new(lens + dir * (self.near / dir.dot(&b.d)), dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed_random;
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        let d = a - b;
+        d.dot(&d).sqrt() < 1e-9
+    }
+
+    fn camera() -> Camera {
+        Camera::look_at(
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(4.0, -2.0, 3.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+        )
+    }
+
+    #[test]
+    fn basis() {
+        let c = camera();
+        let b = c.basis(400, 200);
+        assert!(near(b.d, Vec3::new(0.6, -0.8, 0.0)));
+        assert!(b.cx.dot(&b.d).abs() < 1e-12 && b.cy.dot(&b.d).abs() < 1e-12);
+        assert!(b.cx.dot(&b.cy).abs() < 1e-12);
+        // the image is fov high and twice as wide at unit distance
+        let t = 2.0 * (30.0f64).to_radians().tan();
+        assert!((b.cy.dot(&b.cy).sqrt() - t).abs() < 1e-12);
+        assert!((b.cx.dot(&b.cx).sqrt() - 2.0 * t).abs() < 1e-12);
+        // x goes right and y up on the screen
+        assert!(b.cx.z > 0.0 && b.cy.y > 0.0);
+        // the middle of the screen looks at the target
+        let r = c.ray(&b, 0.0, 0.0).unwrap();
+        assert!(near(r.o, c.o) && near(r.d, b.d));
+    }
+
+    #[test]
+    fn smallpt() {
+        // smallpt's own cx = (w * .5135 / h, 0, 0) and cy = (cx % d).norm() * .5135
+        let b = Camera::smallpt().basis(1024, 768);
+        let d = Vec3::new(0.0, -0.042612, -1.0).norm();
+        let cx = Vec3::new(1024.0 * 0.5135 / 768.0, 0.0, 0.0);
+        let cy = (cx % d).norm() * 0.5135;
+        assert!(near(b.d, d));
+        assert!((b.cx - cx).dot(&(b.cx - cx)).sqrt() < 1e-6);
+        assert!((b.cy - cy).dot(&(b.cy - cy)).sqrt() < 1e-6);
+    }
+
+    #[test]
+    fn looking_along_up() {
+        let c = Camera::look_at(
+            Vec3::zero(),
+            Vec3::new(0.0, 10.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+        );
+        let b = c.basis(100, 100);
+        assert!(b.cx.dot(&b.cx) > 0.0 && b.cy.dot(&b.cy) > 0.0);
+        assert!(b.cx.dot(&b.d).abs() < 1e-12 && b.cy.dot(&b.d).abs() < 1e-12);
+    }
+
+    #[test]
+    fn thin_lens() {
+        seed_random(1);
+        let mut c = camera();
+        c.aperture = 0.5;
+        c.focus = 8.0;
+        let b = c.basis(400, 200);
+        for &(sx, sy) in &[(0.0, 0.0), (0.3, -0.2), (-0.5, 0.5)] {
+            // every ray through a screen point crosses the focal plane at the
+            // same spot, from somewhere on the lens
+            let p = c.o + (b.cx * sx + b.cy * sy + b.d) * c.focus;
+            for _ in 0..100 {
+                let r = c.ray(&b, sx, sy).unwrap();
+                let lens = r.o - c.o;
+                assert!(lens.dot(&lens).sqrt() <= c.aperture + 1e-12);
+                assert!(lens.dot(&b.d).abs() < 1e-12);
+                let t = (p - r.o).dot(&b.d) / r.d.dot(&b.d);
+                assert!((r.o + r.d * t - p).dot(&(r.o + r.d * t - p)).sqrt() < 1e-9);
+            }
+        }
+        // with no focus distance it focuses on the target
+        c.focus = 0.0;
+        assert!((c.focus_distance() - 5.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn names() {
+        for (n, p) in Projection::NAMES {
+            assert!(Projection::from_name(n) == Some(p));
+            assert_eq!(p.name(), n);
+        }
+        assert!(Projection::from_name("cube").is_none());
+    }
+
+    #[test]
+    fn orthographic() {
+        let mut c = camera();
+        c.projection = Projection::Orthographic;
+        c.near = 1.0;
+        let b = c.basis(400, 200);
+        // parallel rays, as wide at any distance as perspective at the target
+        let r = c.ray(&b, 0.5, -0.5).unwrap();
+        assert!(near(r.d, b.d));
+        let want = c.o + (b.cx * 0.5 - b.cy * 0.5) * 5.0 + b.d;
+        assert!(near(r.o, want));
+    }
+
+    #[test]
+    fn fisheye() {
+        let mut c = camera();
+        c.projection = Projection::Fisheye;
+        let b = c.basis(400, 200);
+        let angle = |sx, sy| {
+            let r: Ray = c.ray(&b, sx, sy).unwrap();
+            assert!(near(r.o, c.o));
+            r.d.dot(&b.d).acos().to_degrees()
+        };
+        assert!(near(c.ray(&b, 0.0, 0.0).unwrap().d, b.d));
+        // fov/2 at the top edge, growing linearly towards it
+        assert!((angle(0.0, 0.5) - 30.0).abs() < 1e-9);
+        assert!((angle(0.0, -0.25) - 15.0).abs() < 1e-9);
+        // the image circle touches the top and bottom; x is scaled by the aspect
+        assert!((angle(0.25, 0.0) - 30.0).abs() < 1e-9);
+        assert!(c.ray(&b, 0.3, 0.0).is_none());
+        assert!(c.ray(&b, 0.5, 0.5).is_none());
+    }
+
+    #[test]
+    fn equirect() {
+        let mut c = camera();
+        c.projection = Projection::Equirect;
+        let b = c.basis(400, 200);
+        let dir = |sx, sy| c.ray(&b, sx, sy).unwrap().d;
+        let (right, up) = (b.cx.norm(), b.cy.norm());
+        assert!(near(dir(0.0, 0.0), b.d));
+        assert!(near(dir(0.25, 0.0), right));
+        assert!(near(dir(-0.25, 0.0), right * -1.0));
+        assert!(near(dir(0.5, 0.0), b.d * -1.0));
+        assert!(near(dir(0.1, 0.5), up));
+        assert!(near(dir(-0.3, -0.5), up * -1.0));
+    }
+}
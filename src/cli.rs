@@ -0,0 +1,248 @@
+// Command-line options.
+
+use crate::camera::Projection;
+
+pub const USAGE: &str = "\
+usage: rust-smallpt [options] [spp] [scene]
+
+options:
+  -s, --spp N          samples per subpixel (4 subpixels per pixel)
+      --width N        image width in pixels
+      --height N       image height in pixels
+      --scene NAME     built-in scene to render (see --list)
+  -f, --file PATH      load the scene from a scene file
+      --export PATH    write the scene to a scene file instead of rendering
+  -o, --output PATH    output image (default image.png)
+      --format FMT     png, ppm, jpg, bmp, tga or tiff (default: from --output)
+  -j, --threads N      number of render threads (default: all cores)
+      --projection P   perspective, ortho, fisheye or equirect
+      --max-depth N    maximum path length
+      --rr-depth N     bounces before Russian roulette starts (default 5)
+      --seed N         seed the random numbers for reproducible images
+      --weigh-lights   pick lights by how much of their light is seen, not power
+      --bench          time the render with and without the BVH (no output)
+  -q, --quiet          no progress output
+  -v, --verbose        print scene and timing information
+  -l, --list           list the built-in scenes
+      --help           show this message";
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+pub struct Options {
+    pub scene: Option<String>,
+    pub file: Option<String>,
+    pub export: Option<String>,
+    pub output: String,
+    pub format: Option<String>,
+    pub w: Option<usize>,
+    pub h: Option<usize>,
+    pub samps: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub rr_depth: Option<usize>,
+    pub projection: Option<Projection>,
+    pub seed: Option<u64>,
+    pub weigh_lights: bool,
+    pub threads: Option<usize>,
+    pub verbosity: Verbosity,
+    pub list: bool,
+    pub bench: bool,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            scene: None,
+            file: None,
+            export: None,
+            output: "image.png".to_string(),
+            format: None,
+            w: None,
+            h: None,
+            samps: None,
+            max_depth: None,
+            rr_depth: None,
+            projection: None,
+            seed: None,
+            weigh_lights: false,
+            threads: None,
+            verbosity: Verbosity::Normal,
+            list: false,
+            bench: false,
+            help: false,
+        }
+    }
+}
+
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut opt = Options::default();
+    let mut pos: Vec<String> = Vec::new();
+    while let Some(a) = args.next() {
+        // --name=value is accepted as well as --name value
+        let (name, inline) = match a.split_once('=') {
+            Some((n, v)) if a.starts_with("--") => (n.to_string(), Some(v.to_string())),
+            _ => (a.clone(), None),
+        };
+        let mut value = || match inline.clone().or_else(|| args.next()) {
+            Some(v) => Ok(v),
+            None => Err(format!("{} requires a value", name)),
+        };
+        match name.as_str() {
+            "-s" | "--spp" => opt.samps = Some(positive(&name, &value()?)?),
+            "--width" => opt.w = Some(positive(&name, &value()?)?),
+            "--height" => opt.h = Some(positive(&name, &value()?)?),
+            "--scene" => opt.scene = Some(value()?),
+            "-f" | "--file" => opt.file = Some(value()?),
+            "--export" => opt.export = Some(value()?),
+            "-o" | "--output" => opt.output = value()?,
+            "--format" => opt.format = Some(value()?),
+            "-j" | "--threads" => opt.threads = Some(positive(&name, &value()?)?),
+            "--max-depth" => opt.max_depth = Some(positive(&name, &value()?)?),
+            "--rr-depth" => {
+                let v = value()?;
+                let n = v.parse().map_err(|_| {
+                    format!("{}: invalid value '{}' (expected an integer)", name, v)
+                })?;
+                opt.rr_depth = Some(n);
+            }
+            "--projection" => {
+                let v = value()?;
+                let p = Projection::from_name(&v).ok_or_else(|| {
+                    format!(
+                        "{}: invalid value '{}' (expected perspective, ortho, fisheye or equirect)",
+                        name, v
+                    )
+                })?;
+                opt.projection = Some(p);
+            }
+            "--seed" => {
+                let v = value()?;
+                let seed = v.parse().map_err(|_| {
+                    format!("{}: invalid value '{}' (expected an integer)", name, v)
+                })?;
+                opt.seed = Some(seed);
+            }
+            "-q" | "--quiet" => opt.verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => opt.verbosity = Verbosity::Verbose,
+            "-l" | "--list" => opt.list = true,
+            "--weigh-lights" => opt.weigh_lights = true,
+            "--bench" => opt.bench = true,
+            "--help" => opt.help = true,
+            _ if a.starts_with('-') && a.len() > 1 => {
+                return Err(format!("unknown option '{}'", a));
+            }
+            _ => pos.push(a),
+        }
+    }
+
+    // the old `rust-smallpt [spp] [scene]` form still works
+    let mut pos = pos.into_iter();
+    if let Some(a) = pos.next() {
+        if opt.samps.is_some() {
+            return Err(format!("unexpected argument '{}'", a));
+        }
+        opt.samps = Some(positive("spp", &a)?);
+    }
+    if let Some(a) = pos.next() {
+        if opt.scene.is_some() {
+            return Err(format!("unexpected argument '{}'", a));
+        }
+        opt.scene = Some(a);
+    }
+    if let Some(a) = pos.next() {
+        return Err(format!("unexpected argument '{}'", a));
+    }
+    if opt.scene.is_some() && opt.file.is_some() {
+        return Err("--scene and --file cannot be used together".to_string());
+    }
+    Ok(opt)
+}
+
+fn positive(name: &str, v: &str) -> Result<usize, String> {
+    match v.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "{}: invalid value '{}' (expected a positive integer)",
+            name, v
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Result<Options, String> {
+        parse(s.split_whitespace().map(String::from))
+    }
+
+    fn error(s: &str) -> String {
+        match args(s) {
+            Ok(_) => panic!("'{}' parsed", s),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn options() {
+        let o = args("-s 8 --width=320 --height 240 -o out.ppm --format ppm -j 2").unwrap();
+        assert_eq!(
+            (o.samps, o.w, o.h, o.threads),
+            (Some(8), Some(320), Some(240), Some(2))
+        );
+        assert_eq!(o.output, "out.ppm");
+        assert_eq!(o.format.as_deref(), Some("ppm"));
+        let o = args("--projection fisheye --rr-depth 0 --seed 7 --weigh-lights -q").unwrap();
+        assert!(o.projection == Some(Projection::Fisheye));
+        assert_eq!((o.rr_depth, o.seed), (Some(0), Some(7)));
+        assert!(o.weigh_lights && o.verbosity == Verbosity::Quiet);
+        let o = args("").unwrap();
+        assert_eq!(o.output, "image.png");
+        assert!(o.samps.is_none() && o.scene.is_none() && !o.weigh_lights);
+    }
+
+    #[test]
+    fn positional() {
+        let o = args("16 cornell").unwrap();
+        assert_eq!(o.samps, Some(16));
+        assert_eq!(o.scene.as_deref(), Some("cornell"));
+        assert_eq!(error("-s 4 16"), "unexpected argument '16'");
+        assert_eq!(error("--scene a 16 b"), "unexpected argument 'b'");
+        assert_eq!(error("1 a b"), "unexpected argument 'b'");
+        assert_eq!(
+            error("x"),
+            "spp: invalid value 'x' (expected a positive integer)"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("--spp 0"),
+            "--spp: invalid value '0' (expected a positive integer)"
+        );
+        assert_eq!(
+            error("--width=-3"),
+            "--width: invalid value '-3' (expected a positive integer)"
+        );
+        assert_eq!(error("--height"), "--height requires a value");
+        assert_eq!(error("--bogus"), "unknown option '--bogus'");
+        assert_eq!(
+            error("--seed x"),
+            "--seed: invalid value 'x' (expected an integer)"
+        );
+        assert_eq!(
+            error("--projection cube"),
+            "--projection: invalid value 'cube' (expected perspective, ortho, fisheye or equirect)"
+        );
+        assert_eq!(
+            error("--scene a -f b.scene"),
+            "--scene and --file cannot be used together"
+        );
+    }
+}
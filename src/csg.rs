@@ -0,0 +1,230 @@
+// Constructive solid geometry: union, intersection and difference of solids.
+
+use crate::bvh::Aabb;
+use crate::instance::Group;
+use crate::shape::{Hit, Shape, Span};
+use crate::{Ray, EPS};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Op {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl Op {
+    pub fn name(self) -> &'static str {
+        match self {
+            Op::Union => "union",
+            Op::Intersection => "intersection",
+            Op::Difference => "difference",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Op> {
+        match s {
+            "union" => Some(Op::Union),
+            "intersection" => Some(Op::Intersection),
+            "difference" => Some(Op::Difference),
+            _ => None,
+        }
+    }
+
+    fn inside(self, a: bool, b: bool) -> bool {
+        match self {
+            Op::Union => a || b,
+            Op::Intersection => a && b,
+            Op::Difference => a && !b,
+        }
+    }
+}
+
+// The operands are groups so that the scene file can name them.
+pub struct Csg {
+    pub op: Op,
+    pub a: Arc<Group>,
+    pub b: Arc<Group>,
+}
+
+impl Csg {
+    pub fn new(op: Op, a: Arc<Group>, b: Arc<Group>) -> Csg {
+        Csg { op, a, b }
+    }
+}
+
+impl Shape for Csg {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        for s in self.spans(ray) {
+            for h in [s.enter, s.exit] {
+                if h.t >= t_max {
+                    return None;
+                }
+                if h.t > EPS {
+                    return Some(h);
+                }
+            }
+        }
+        None
+    }
+
+    fn bounds(&self) -> Aabb {
+        let (a, b) = (self.a.bounds(), self.b.bounds());
+        match self.op {
+            Op::Union => a.union(&b),
+            Op::Intersection => a.overlap(&b),
+            Op::Difference => a,
+        }
+    }
+
+    fn material(&self) -> Option<usize> {
+        None
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "csg op={} a={} b={}",
+            self.op.name(),
+            self.a.name,
+            self.b.name
+        )
+    }
+
+    fn solid(&self) -> bool {
+        true
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        combine(self.op, self.a.spans(ray), self.b.spans(ray))
+    }
+}
+
+// Walks the boundaries of both span lists in order, keeping those where
+// being inside the result changes. Surfaces of b that bound a difference
+// face the other way and show the material of a, which they cut into.
+pub fn combine(op: Op, a: Vec<Span>, b: Vec<Span>) -> Vec<Span> {
+    let mut ev: Vec<(Hit, bool, bool)> = Vec::with_capacity(2 * (a.len() + b.len()));
+    for (spans, is_b) in [(a, false), (b, true)] {
+        for s in spans {
+            ev.push((s.enter, is_b, true));
+            ev.push((s.exit, is_b, false));
+        }
+    }
+    ev.sort_by(|x, y| x.0.t.total_cmp(&y.0.t));
+
+    let mut out: Vec<Span> = Vec::new();
+    let (mut in_a, mut in_b) = (false, false);
+    let mut a_mat = 0;
+    let mut enter: Option<Hit> = None;
+    for (mut h, is_b, entering) in ev {
+        let was = op.inside(in_a, in_b);
+        if is_b {
+            in_b = entering;
+        } else {
+            in_a = entering;
+            if entering {
+                a_mat = h.mat;
+            }
+        }
+        let now = op.inside(in_a, in_b);
+        if was == now {
+            continue;
+        }
+        if is_b && op == Op::Difference {
+            h.n = h.n * -1.0;
+            h.mat = a_mat;
+        }
+        if now {
+            enter = Some(h);
+        } else if let Some(e) = enter.take() {
+            // solids that touch make one span, not two with a face between
+            match out.last_mut() {
+                Some(last) if e.t - last.exit.t < EPS => last.exit = h,
+                _ => out.push(Span { enter: e, exit: h }),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shape::Sphere;
+    use crate::Vec3;
+
+    // Spans of op on spheres of radius ra and rb centred at xa and xb on the
+    // x axis, materials 1 and 2, along +x from x = -100.
+    fn spans(op: Op, (xa, ra): (f64, f64), (xb, rb): (f64, f64)) -> Vec<Span> {
+        let r = Ray::new(Vec3::new(-100.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let a = Sphere::new(ra, Vec3::new(xa, 0.0, 0.0), 1);
+        let b = Sphere::new(rb, Vec3::new(xb, 0.0, 0.0), 2);
+        combine(op, a.spans(&r), b.spans(&r))
+    }
+
+    // entry and exit distances
+    fn ts(spans: &[Span]) -> Vec<(f64, f64)> {
+        spans.iter().map(|s| (s.enter.t, s.exit.t)).collect()
+    }
+
+    fn close(got: Vec<(f64, f64)>, want: &[(f64, f64)]) {
+        assert_eq!(got.len(), want.len(), "{:?}", got);
+        for (g, w) in got.iter().zip(want) {
+            assert!(
+                (g.0 - w.0).abs() < 1e-9 && (g.1 - w.1).abs() < 1e-9,
+                "{:?}",
+                got
+            );
+        }
+    }
+
+    // (material, x of the normal) of a hit
+    fn side(h: &Hit) -> (usize, f64) {
+        (h.mat, h.n.x)
+    }
+
+    // a from 90 to 110, b from 105 to 125
+    #[test]
+    fn overlapping() {
+        let (a, b) = ((0.0, 10.0), (15.0, 10.0));
+        let u = spans(Op::Union, a, b);
+        close(ts(&u), &[(90.0, 125.0)]);
+        assert_eq!((side(&u[0].enter), side(&u[0].exit)), ((1, -1.0), (2, 1.0)));
+        let i = spans(Op::Intersection, a, b);
+        close(ts(&i), &[(105.0, 110.0)]);
+        assert_eq!((side(&i[0].enter), side(&i[0].exit)), ((2, -1.0), (1, 1.0)));
+        // b's surface faces back into a and takes a's material
+        let d = spans(Op::Difference, a, b);
+        close(ts(&d), &[(90.0, 105.0)]);
+        assert_eq!((side(&d[0].enter), side(&d[0].exit)), ((1, -1.0), (1, 1.0)));
+    }
+
+    // a from 90 to 110, b from 110 to 130
+    #[test]
+    fn touching() {
+        let (a, b) = ((0.0, 10.0), (20.0, 10.0));
+        let u = spans(Op::Union, a, b);
+        close(ts(&u), &[(90.0, 130.0)]);
+        assert_eq!((side(&u[0].enter), side(&u[0].exit)), ((1, -1.0), (2, 1.0)));
+        close(ts(&spans(Op::Intersection, a, b)), &[]);
+        let d = spans(Op::Difference, a, b);
+        close(ts(&d), &[(90.0, 110.0)]);
+        assert_eq!(side(&d[0].exit), (1, 1.0));
+    }
+
+    // a from 90 to 110 around b from 95 to 105
+    #[test]
+    fn nested() {
+        let (a, b) = ((0.0, 10.0), (0.0, 5.0));
+        close(ts(&spans(Op::Union, a, b)), &[(90.0, 110.0)]);
+        let i = spans(Op::Intersection, a, b);
+        close(ts(&i), &[(95.0, 105.0)]);
+        assert_eq!((side(&i[0].enter), side(&i[0].exit)), ((2, -1.0), (2, 1.0)));
+        let d = spans(Op::Difference, a, b);
+        close(ts(&d), &[(90.0, 95.0), (105.0, 110.0)]);
+        assert_eq!((side(&d[0].exit), side(&d[1].enter)), ((1, 1.0), (1, -1.0)));
+        // nothing is left of the smaller one
+        close(ts(&spans(Op::Difference, b, a)), &[]);
+        close(ts(&spans(Op::Union, b, a)), &[(90.0, 110.0)]);
+    }
+}
@@ -0,0 +1,284 @@
+// Light from all around the scene for rays that escape it.
+
+use crate::light::luminance;
+use crate::scenefile::resolve;
+use crate::texture::Image;
+use crate::{random, Vec3};
+use std::f64::consts::PI;
+use std::path::Path;
+
+pub trait Environment: Send + Sync {
+    // light arriving from direction d
+    fn radiance(&self, d: Vec3) -> Vec3;
+    // A direction for direct lighting and its density over solid angle;
+    // None if there is nothing to sample.
+    fn sample(&self) -> Option<(Vec3, f64)>;
+    // density with which sample() picks d
+    fn pdf(&self, d: Vec3) -> f64;
+    // scene file line
+    fn export(&self) -> String;
+}
+
+// An equirectangular image with +y at the top row and -z, where the smallpt
+// camera looks, in the middle. Directions are sampled in proportion to the
+// brightness of the pixels, weighted by the solid angle each covers.
+pub struct Equirect {
+    img: Image,
+    // degrees about +y
+    rotate: f64,
+    // running totals of the pixel weights along each row, and of the rows
+    cols: Vec<f64>,
+    rows: Vec<f64>,
+}
+
+impl Equirect {
+    pub fn new(img: Image, rotate: f64) -> Equirect {
+        let (w, h) = (img.w, img.h);
+        let mut cols = Vec::with_capacity(w * h);
+        let mut rows = Vec::with_capacity(h);
+        let mut total = 0.0;
+        for j in 0..h {
+            let sin = (PI * (j as f64 + 0.5) / h as f64).sin();
+            let mut row = 0.0;
+            for i in 0..w {
+                row += luminance(img.px[j * w + i]) * sin;
+                cols.push(row);
+            }
+            total += row;
+            rows.push(total);
+        }
+        Equirect {
+            img,
+            rotate,
+            cols,
+            rows,
+        }
+    }
+
+    // image coordinates of d, u from 0 to 1 left to right and v from 0 at
+    // the top
+    fn uv(&self, d: Vec3) -> (f64, f64) {
+        let phi = d.x.atan2(-d.z);
+        let u = (0.5 + phi / (2.0 * PI) - self.rotate / 360.0).rem_euclid(1.0);
+        (u, d.y.clamp(-1.0, 1.0).acos() / PI)
+    }
+
+    fn pixel(&self, u: f64, v: f64) -> (usize, usize) {
+        let i = ((u * self.img.w as f64) as usize).min(self.img.w - 1);
+        let j = ((v * self.img.h as f64) as usize).min(self.img.h - 1);
+        (i, j)
+    }
+
+    pub fn value(&self, d: Vec3) -> Vec3 {
+        let (u, v) = self.uv(d);
+        self.img.value(u, 1.0 - v)
+    }
+
+    // light arriving from all directions, by luminance
+    pub fn total(&self) -> f64 {
+        let (w, h) = (self.img.w, self.img.h);
+        self.rows.last().map_or(0.0, |&t| t) * 2.0 * PI * PI / (w * h) as f64
+    }
+
+    pub fn sample(&self) -> Option<(Vec3, f64)> {
+        let total = *self.rows.last()?;
+        if total <= 0.0 {
+            return None;
+        }
+        let w = self.img.w;
+        let x = random() * total;
+        let j = self.rows.partition_point(|&r| r <= x).min(self.img.h - 1);
+        let row = &self.cols[j * w..(j + 1) * w];
+        let x = random() * row[w - 1];
+        let i = row.partition_point(|&c| c <= x).min(w - 1);
+        let u = (i as f64 + random()) / w as f64;
+        let v = (j as f64 + random()) / self.img.h as f64;
+        let d = equirect_dir(u + self.rotate / 360.0, v);
+        let pdf = self.pdf(d);
+        if pdf > 0.0 {
+            Some((d, pdf))
+        } else {
+            None
+        }
+    }
+
+    pub fn pdf(&self, d: Vec3) -> f64 {
+        let total = match self.rows.last() {
+            Some(&t) if t > 0.0 => t,
+            _ => return 0.0,
+        };
+        let (u, v) = self.uv(d);
+        let sin = (v * PI).sin();
+        if sin <= 0.0 {
+            return 0.0;
+        }
+        let (i, j) = self.pixel(u, v);
+        let w = self.img.w;
+        let c = self.cols[j * w + i] - if i > 0 { self.cols[j * w + i - 1] } else { 0.0 };
+        // over the image, then over the sphere
+        let p = c / total * (w * self.img.h) as f64;
+        p / (2.0 * PI * PI * sin)
+    }
+}
+
+// The direction through image coordinates u, v of an unrotated map.
+pub fn equirect_dir(u: f64, v: f64) -> Vec3 {
+    let phi = (u - 0.5) * 2.0 * PI;
+    let theta = v * PI;
+    let (s, c) = theta.sin_cos();
+    Vec3::new(s * phi.sin(), c, -s * phi.cos())
+}
+
+// An HDR image from a file.
+pub struct EnvMap {
+    // absolute path of the image, for export
+    pub file: String,
+    pub rotate: f64,
+    // multiplies the pixels
+    pub intensity: f64,
+    map: Equirect,
+}
+
+impl EnvMap {
+    pub fn load(file: &str, dir: &Path, rotate: f64, intensity: f64) -> Result<EnvMap, String> {
+        let file = resolve(file, dir)?;
+        let map = Equirect::new(Image::load(&file)?, rotate);
+        Ok(EnvMap {
+            file,
+            rotate,
+            intensity,
+            map,
+        })
+    }
+}
+
+impl Environment for EnvMap {
+    fn radiance(&self, d: Vec3) -> Vec3 {
+        self.map.value(d) * self.intensity
+    }
+
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        self.map.sample()
+    }
+
+    fn pdf(&self, d: Vec3) -> f64 {
+        self.map.pdf(d)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "environment file={} rotate={} intensity={}",
+            self.file, self.rotate, self.intensity
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed_random;
+
+    // 8 x 4, dim with one bright pixel and a black one
+    fn map(rotate: f64) -> Equirect {
+        let (w, h) = (8, 4);
+        let mut px = vec![Vec3::new(0.5, 0.2, 0.1); w * h];
+        px[w + 2] = Vec3::new(20.0, 20.0, 20.0);
+        px[2 * w + 5] = Vec3::zero();
+        Equirect::new(Image { w, h, px }, rotate)
+    }
+
+    fn uniform_dir() -> Vec3 {
+        let y = 1.0 - 2.0 * random();
+        let r = (1.0 - y * y).sqrt();
+        let (s, c) = (2.0 * PI * random()).sin_cos();
+        Vec3::new(r * c, y, r * s)
+    }
+
+    #[test]
+    fn directions() {
+        let m = map(0.0);
+        // -z in the middle, +y at the top
+        let d = equirect_dir(0.5, 0.5);
+        assert!((d.z + 1.0).abs() < 1e-12);
+        assert!((equirect_dir(0.3, 0.0).y - 1.0).abs() < 1e-12);
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.3, 0.99)] {
+            let (u2, v2) = m.uv(equirect_dir(u, v));
+            assert!((u - u2).abs() < 1e-12 && (v - v2).abs() < 1e-12);
+        }
+        // turned a quarter about +y, what was at u is at u + 0.25
+        let r = map(90.0);
+        let (u, v) = r.uv(equirect_dir(0.6, 0.3));
+        assert!((u - 0.35).abs() < 1e-12 && (v - 0.3).abs() < 1e-12);
+    }
+
+    // The share of samples landing in each pixel is what pdf() integrates
+    // to over it, with or without rotation.
+    #[test]
+    fn sample_matches_pdf() {
+        seed_random(19);
+        for rotate in [0.0, 90.0] {
+            let m = map(rotate);
+            let (w, h) = (8, 4);
+            let n = 200000;
+            let mut count = vec![0; w * h];
+            for _ in 0..n {
+                let (d, pdf) = m.sample().unwrap();
+                assert!((pdf - m.pdf(d)).abs() < 1e-9 * pdf);
+                let (u, v) = m.uv(d);
+                let (i, j) = m.pixel(u, v);
+                count[j * w + i] += 1;
+            }
+            assert_eq!(count[2 * w + 5], 0);
+            for j in 0..h {
+                for i in 0..w {
+                    let (u, v) = ((i as f64 + 0.5) / w as f64, (j as f64 + 0.5) / h as f64);
+                    let d = equirect_dir(u + rotate / 360.0, v);
+                    // pdf() goes as 1 / sin across the pixel, so this is its
+                    // integral over it
+                    let area = 2.0 * PI / w as f64 * PI / h as f64 * (v * PI).sin();
+                    let want = m.pdf(d) * area;
+                    let got = count[j * w + i] as f64 / n as f64;
+                    assert!((got - want).abs() < 0.003 + 0.02 * want, "{} {}", got, want);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_integrates() {
+        seed_random(20);
+        let m = map(30.0);
+        let n = 400000;
+        let total: f64 = (0..n).map(|_| m.pdf(uniform_dir()) * 4.0 * PI).sum::<f64>() / n as f64;
+        assert!((total - 1.0).abs() < 0.02, "{}", total);
+        // an all black map has nothing to sample
+        let black = Equirect::new(
+            Image {
+                w: 2,
+                h: 2,
+                px: vec![Vec3::zero(); 4],
+            },
+            0.0,
+        );
+        assert!(black.sample().is_none());
+        assert_eq!(black.pdf(Vec3::new(0.0, 1.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn total() {
+        // near 4 pi for a uniform map of luminance 1
+        let m = Equirect::new(
+            Image {
+                w: 16,
+                h: 64,
+                px: vec![Vec3::new(1.0, 1.0, 1.0); 16 * 64],
+            },
+            0.0,
+        );
+        assert!(
+            (m.total() - 4.0 * PI).abs() < 1e-3 * 4.0 * PI,
+            "{}",
+            m.total()
+        );
+    }
+}
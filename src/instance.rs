@@ -0,0 +1,284 @@
+// Transformed objects and groups of shared geometry.
+
+use crate::bvh::Aabb;
+use crate::csg::{combine, Op};
+use crate::scene::{closest, Accel, BVH_MIN_PRIMS};
+use crate::scenefile::vec_str;
+use crate::shape::{Hit, Shape, Span};
+use crate::transform::Transform;
+use crate::{Ray, Vec3};
+use std::sync::Arc;
+
+// An object moved into place by xf. The object itself is shared, so a group
+// can be placed any number of times without copying it.
+pub struct Instance {
+    pub obj: Arc<dyn Shape>,
+    pub xf: Transform,
+}
+
+impl Instance {
+    pub fn new(obj: Arc<dyn Shape>, xf: Transform) -> Instance {
+        Instance { obj, xf }
+    }
+
+    // Whether samples of the object stay uniform over its surface once
+    // moved into place; scaling it unevenly would stretch some parts more
+    // than others.
+    fn uniform(&self) -> bool {
+        let s = self.xf.scale;
+        s.x.abs() == s.y.abs() && s.y.abs() == s.z.abs()
+    }
+
+    // object space ray and the factor from world to object distances
+    fn local(&self, ray: &Ray) -> (Ray, f64) {
+        let d = self.xf.inverse_vector(ray.d);
+        let len = d.dot(&d).sqrt();
+        (Ray::new(self.xf.inverse_point(ray.o), d * (1.0 / len)), len)
+    }
+
+    fn world(&self, ray: &Ray, h: Hit, len: f64) -> Hit {
+        let t = h.t / len;
+        Hit {
+            t,
+            p: ray.o + ray.d * t,
+            n: self.xf.normal(h.n),
+            uv: h.uv,
+            mat: h.mat,
+        }
+    }
+}
+
+impl Shape for Instance {
+    // The ray is taken into object space and renormalised, which stretches
+    // distances along it by len.
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        let (local, len) = self.local(ray);
+        let h = self.obj.intersect(&local, t_max * len)?;
+        Some(self.world(ray, h, len))
+    }
+
+    fn bounds(&self) -> Aabb {
+        let b = self.obj.bounds();
+        if !b.is_finite() {
+            return Aabb::infinite();
+        }
+        let corners: Vec<_> = (0..8)
+            .map(|i| {
+                let x = if i & 1 == 0 { b.min.x } else { b.max.x };
+                let y = if i & 2 == 0 { b.min.y } else { b.max.y };
+                let z = if i & 4 == 0 { b.min.z } else { b.max.z };
+                self.xf.point(Vec3::new(x, y, z))
+            })
+            .collect();
+        Aabb::from_points(&corners)
+    }
+
+    fn material(&self) -> Option<usize> {
+        self.obj.material()
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "{} scale={} rotate={} translate={}",
+            self.obj.export(),
+            vec_str(self.xf.scale),
+            vec_str(self.xf.rotate),
+            vec_str(self.xf.translate)
+        )
+    }
+
+    // Zero, as if unknown, unless the scaling is uniform.
+    fn area(&self) -> f64 {
+        if !self.uniform() {
+            return 0.0;
+        }
+        self.obj.area() * self.xf.scale.x * self.xf.scale.x
+    }
+
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        if !self.uniform() {
+            return None;
+        }
+        let (p, n) = self.obj.sample()?;
+        Some((self.xf.point(p), self.xf.normal(n)))
+    }
+
+    fn solid(&self) -> bool {
+        self.obj.solid()
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let (local, len) = self.local(ray);
+        self.obj
+            .spans(&local)
+            .into_iter()
+            .map(|s| Span {
+                enter: self.world(ray, s.enter, len),
+                exit: self.world(ray, s.exit, len),
+            })
+            .collect()
+    }
+}
+
+// Named list of objects in their own coordinates, with a BVH of its own when
+// it is big enough.
+pub struct Group {
+    pub name: String,
+    pub objects: Vec<Box<dyn Shape>>,
+    accel: Option<Accel>,
+}
+
+impl Group {
+    pub fn new(name: &str, objects: Vec<Box<dyn Shape>>) -> Group {
+        let prims: usize = objects.iter().map(|o| o.parts()).sum();
+        let accel = if prims >= BVH_MIN_PRIMS {
+            Some(Accel::build(&objects))
+        } else {
+            None
+        };
+        Group {
+            name: name.to_string(),
+            objects,
+            accel,
+        }
+    }
+}
+
+impl Shape for Group {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        match &self.accel {
+            Some(a) => a.intersect(&self.objects, ray, t_max),
+            None => closest(self.objects.iter().map(|o| &**o), ray, t_max),
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.objects
+            .iter()
+            .fold(Aabb::empty(), |b, o| b.union(&o.bounds()))
+    }
+
+    fn material(&self) -> Option<usize> {
+        None
+    }
+
+    // a group only appears in the scene through an Instance
+    fn export(&self) -> String {
+        format!("instance group={}", self.name)
+    }
+
+    // the union of its members
+    fn solid(&self) -> bool {
+        self.objects.iter().all(|o| o.solid())
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        self.objects
+            .iter()
+            .fold(Vec::new(), |acc, o| combine(Op::Union, acc, o.spans(ray)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed_random;
+    use crate::shape::Sphere;
+
+    fn sphere(rad: f64, x: f64) -> Arc<dyn Shape> {
+        Arc::new(Sphere::new(rad, Vec3::new(x, 0.0, 0.0), 1))
+    }
+
+    fn instance(obj: Arc<dyn Shape>, scale: Vec3, translate: Vec3) -> Instance {
+        Instance::new(
+            obj,
+            Transform::new(scale, Vec3::new(0.0, 0.0, 45.0), translate),
+        )
+    }
+
+    fn along_x(y: f64) -> Ray {
+        Ray::new(Vec3::new(-100.0, y, 0.0), Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn intersect() {
+        // a unit sphere scaled up to 10 about x = 50
+        let i = instance(
+            sphere(1.0, 0.0),
+            Vec3::new(10.0, 10.0, 10.0),
+            Vec3::new(50.0, 0.0, 0.0),
+        );
+        let h = i.intersect(&along_x(0.0), 1e20).unwrap();
+        assert!((h.t - 140.0).abs() < 1e-9);
+        assert!((h.p.x - 40.0).abs() < 1e-9 && (h.n.x + 1.0).abs() < 1e-9);
+        assert!(i.intersect(&along_x(0.0), 139.0).is_none());
+        assert!(i.intersect(&along_x(11.0), 1e20).is_none());
+        // the turned bounding box of the sphere, not of the turned sphere
+        let b = i.bounds();
+        let r = 10.0 * 2f64.sqrt();
+        assert!((b.min.x - (50.0 - r)).abs() < 1e-9 && (b.max.y - r).abs() < 1e-9);
+        // an ellipsoid, 20 long along the ray and 5 across
+        let i = Instance::new(
+            sphere(1.0, 0.0),
+            Transform::new(Vec3::new(20.0, 5.0, 5.0), Vec3::zero(), Vec3::zero()),
+        );
+        let h = i.intersect(&along_x(3.0), 1e20).unwrap();
+        assert!((h.t - 84.0).abs() < 1e-9);
+        let s = i.spans(&along_x(3.0));
+        assert_eq!(s.len(), 1);
+        assert!((s[0].exit.t - 116.0).abs() < 1e-9 && s[0].exit.n.x > 0.0);
+        // the normal of an ellipsoid leans towards its flat side
+        let want = Vec3::new(-16.0 / 400.0, 3.0 / 25.0, 0.0).norm();
+        assert!((h.n - want).dot(&(h.n - want)) < 1e-18);
+    }
+
+    #[test]
+    fn area_and_sample() {
+        seed_random(3);
+        let i = instance(
+            sphere(2.0, 1.0),
+            Vec3::new(-3.0, 3.0, 3.0),
+            Vec3::new(0.0, 5.0, 0.0),
+        );
+        let area = 4.0 * std::f64::consts::PI * 36.0;
+        assert!((i.area() - area).abs() < 1e-9);
+        let c = i.xf.point(Vec3::new(1.0, 0.0, 0.0));
+        for _ in 0..100 {
+            let (p, n) = i.sample().unwrap();
+            let r = p - c;
+            assert!((r.dot(&r).sqrt() - 6.0).abs() < 1e-9);
+            assert!((n - r * (1.0 / 6.0)).dot(&(n - r * (1.0 / 6.0))) < 1e-18);
+        }
+        // not sampled at all when stretched unevenly
+        let i = instance(sphere(2.0, 1.0), Vec3::new(1.0, 2.0, 1.0), Vec3::zero());
+        assert_eq!(i.area(), 0.0);
+        assert!(i.sample().is_none());
+    }
+
+    #[test]
+    fn group() {
+        let objects: Vec<Box<dyn Shape>> = (0..BVH_MIN_PRIMS + 2)
+            .map(|k| {
+                Box::new(Sphere::new(1.0, Vec3::new(3.0 * k as f64, 0.0, 0.0), k)) as Box<dyn Shape>
+            })
+            .collect();
+        let g: Arc<dyn Shape> = Arc::new(Group::new("row", objects));
+        // placed twice, shifted along y
+        for y in [10.0, -10.0] {
+            let i = Instance::new(
+                g.clone(),
+                Transform::new(
+                    Vec3::new(1.0, 1.0, 1.0),
+                    Vec3::zero(),
+                    Vec3::new(0.0, y, 0.0),
+                ),
+            );
+            let h = i.intersect(&along_x(y), 1e20).unwrap();
+            assert!((h.t - 99.0).abs() < 1e-9 && h.mat == 0);
+            let r = Ray::new(Vec3::new(7.5, y, 0.0), Vec3::new(1.0, 0.0, 0.0));
+            assert_eq!(i.intersect(&r, 1e20).unwrap().mat, 3);
+            assert!(i.intersect(&along_x(0.0), 1e20).is_none());
+            assert_eq!(i.spans(&along_x(y)).len(), BVH_MIN_PRIMS + 2);
+        }
+    }
+}
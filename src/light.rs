@@ -0,0 +1,169 @@
+// Emitting objects as lights for direct lighting. Shapes that can sample the
+// directions towards themselves (spheres) do; the rest are sampled uniformly
+// over their area.
+
+use crate::shape::{Hit, Shape};
+use crate::{Ray, Vec3};
+use std::f64::consts::PI;
+
+pub struct Light {
+    // index into Scene::objects
+    pub obj: usize,
+    // emitted flux, estimated as if the surface were a diffuse emitter of
+    // constant luminance
+    pub power: f64,
+    // what lights are picked in proportion to: the power, unless
+    // Scene::weigh_lights() finds out how much of it reaches the scene
+    pub weight: f64,
+    // sampled with sample_from() rather than by area
+    cone: bool,
+}
+
+impl Light {
+    // None for shapes with neither kind of sampling.
+    pub fn new(i: usize, obj: &dyn Shape, e: Vec3) -> Option<Light> {
+        let cone = obj.sample_from(Vec3::zero()).is_some();
+        let area = obj.area();
+        if !cone && (area <= 0.0 || obj.sample().is_none()) {
+            return None;
+        }
+        let power = luminance(e) * area * PI;
+        Some(Light {
+            obj: i,
+            power,
+            weight: power,
+            cone,
+        })
+    }
+
+    // Direction from x towards obj, its density over solid angle and the
+    // distance to the light along it.
+    pub fn sample(&self, obj: &dyn Shape, x: Vec3) -> Option<(Vec3, f64, f64)> {
+        if self.cone {
+            return obj.sample_from(x);
+        }
+        let (p, n) = obj.sample()?;
+        let w = p - x;
+        let d2 = w.dot(&w);
+        let t = d2.sqrt();
+        let wi = w * (1.0 / t);
+        // emitters shine from both sides
+        let cos = n.dot(&wi).abs();
+        if cos < 1e-12 {
+            return None;
+        }
+        Some((wi, d2 / (cos * obj.area()), t))
+    }
+
+    // Density with which sample() would have picked r towards hit on obj.
+    pub fn pdf(&self, obj: &dyn Shape, r: &Ray, hit: &Hit) -> f64 {
+        if self.cone {
+            return obj.pdf_from(r.o, r.d);
+        }
+        let cos = hit.n.dot(&r.d).abs();
+        if cos < 1e-12 {
+            return 0.0;
+        }
+        hit.t * hit.t / (cos * obj.area())
+    }
+}
+
+// Rec. 709 weights, so green light counts for more than blue.
+pub fn luminance(c: Vec3) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Triangle;
+    use crate::prim::Disk;
+    use crate::shape::{basis, Plane, Quad, Sphere};
+    use crate::{random, seed_random, INF};
+
+    // Uniform direction in the cone about w with cos_max, for integrating
+    // over the directions that could reach an object.
+    fn cone_dir(w: Vec3, cos_max: f64) -> Vec3 {
+        let (u, v) = basis(w);
+        let z = 1.0 - random() * (1.0 - cos_max);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let (s, c) = (2.0 * PI * random()).sin_cos();
+        u * (r * c) + v * (r * s) + w * z
+    }
+
+    // Every direction sample() picks from x reaches obj at the distance it
+    // gives, pdf() agrees with its density there, and that density
+    // integrates to one over all directions.
+    fn check(obj: &dyn Shape, x: Vec3) {
+        let light = Light::new(0, obj, Vec3::new(1.0, 1.0, 1.0)).unwrap();
+        let n = 400000;
+        for _ in 0..n / 200 {
+            let (wi, pdf, t) = light.sample(obj, x).unwrap();
+            let r = Ray::new(x, wi);
+            let h = obj.intersect(&r, INF).unwrap();
+            assert!((h.t - t).abs() < 1e-6 * t, "{} {}", h.t, t);
+            let p = light.pdf(obj, &r, &h);
+            assert!((p - pdf).abs() < 1e-6 * pdf, "{} {}", p, pdf);
+        }
+        // over the cone round the bounding sphere, or every direction from
+        // inside it
+        let b = obj.bounds();
+        let c = (b.min + b.max) * 0.5 - x;
+        let (rad2, d2) = ((b.max - b.min).dot(&(b.max - b.min)) * 0.25, c.dot(&c));
+        let cos_max = if d2 > rad2 {
+            (1.0 - rad2 / d2).sqrt()
+        } else {
+            -1.0
+        };
+        let mut total = 0.0;
+        for _ in 0..n {
+            let r = Ray::new(x, cone_dir(c.norm(), cos_max));
+            if let Some(h) = obj.intersect(&r, INF) {
+                total += light.pdf(obj, &r, &h) * 2.0 * PI * (1.0 - cos_max) / n as f64;
+            }
+        }
+        assert!((total - 1.0).abs() < 0.02, "{}", total);
+    }
+
+    #[test]
+    fn sphere() {
+        seed_random(7);
+        let s = Sphere::new(2.0, Vec3::new(1.0, 2.0, 3.0), 0);
+        check(&s, Vec3::new(1.0, 2.0, 6.0));
+        check(&s, Vec3::new(-4.0, 0.0, 0.0));
+        // from inside, every direction
+        check(&s, Vec3::new(1.5, 2.0, 3.0));
+        let light = Light::new(0, &s, Vec3::new(0.0, 1.0, 0.0)).unwrap();
+        assert!((light.power - 0.7152 * 16.0 * PI * PI).abs() < 1e-9);
+        assert_eq!(light.weight, light.power);
+    }
+
+    #[test]
+    fn area() {
+        seed_random(8);
+        let q = Quad::new(
+            Vec3::new(-1.0, 5.0, -2.0),
+            Vec3::new(3.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 2.0),
+            0,
+        );
+        check(&q, Vec3::new(0.5, 4.9, -0.7));
+        // from behind too
+        check(&q, Vec3::new(0.5, 6.1, -1.3));
+        let d = Disk::new(Vec3::new(2.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 1.0), 1.5, 0);
+        check(&d, Vec3::new(1.3, 1.0, 0.7));
+        let t = Triangle::new(
+            Vec3::new(0.0, 4.0, 0.0),
+            Vec3::new(2.0, 4.0, 0.0),
+            Vec3::new(0.0, 4.0, 2.0),
+            0,
+        );
+        check(&t, Vec3::new(0.5, 3.3, 0.5));
+        // the power of a diffuse emitter, pi e area
+        let light = Light::new(0, &t, Vec3::new(2.0, 2.0, 2.0)).unwrap();
+        assert!((light.power - 4.0 * PI).abs() < 1e-9);
+        // nothing to sample on an infinite plane
+        let p = Plane::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 0);
+        assert!(Light::new(0, &p, Vec3::new(1.0, 1.0, 1.0)).is_none());
+    }
+}
@@ -1,16 +1,54 @@
-#[macro_use]
-extern crate lazy_static;
-
+mod bvh;
+mod camera;
+mod cli;
+mod csg;
+mod env;
+mod instance;
+mod light;
+mod material;
+mod medium;
+mod mesh;
+mod microfacet;
+mod prim;
+mod scene;
+mod scenefile;
+mod scenes;
+mod shape;
+mod sky;
+mod texture;
+mod transform;
+
+use cli::Verbosity;
+use env::Environment;
+use material::Material;
+use medium::{Medium, Phase};
+use rand::rngs::SmallRng;
+use rand::{RngExt, SeedableRng};
 use rayon::prelude::*;
+use scene::{Scene, Settings};
+use shape::Hit;
+use std::cell::RefCell;
 use std::fs;
 use std::io::Write;
 use std::ops::{Add, Mul, Rem, Sub};
+use std::time::Instant;
 
 const EPS: f64 = 1.0e-4;
 const INF: f64 = 1.0e20;
+// Edges of volumes a path or shadow ray may cross. They are not bounces, so
+// max_depth doesn't bound them.
+const MAX_CROSSINGS: usize = 256;
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_rng(&mut rand::rng()));
+}
 
 fn random() -> f64 {
-    rand::random::<f64>()
+    RNG.with(|r| r.borrow_mut().random::<f64>())
+}
+
+fn seed_random(seed: u64) {
+    RNG.with(|r| *r.borrow_mut() = SmallRng::seed_from_u64(seed));
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -21,7 +59,7 @@ struct Vec3 {
 }
 
 impl Vec3 {
-    fn new(x: f64, y: f64, z: f64) -> Vec3 {
+    const fn new(x: f64, y: f64, z: f64) -> Vec3 {
         Vec3 { x, y, z }
     }
     fn zero() -> Vec3 {
@@ -32,13 +70,13 @@ impl Vec3 {
     }
     fn norm(mut self) -> Vec3 {
         let l = 1.0 / (self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
-        self.x = self.x * l;
-        self.y = self.y * l;
-        self.z = self.z * l;
+        self.x *= l;
+        self.y *= l;
+        self.z *= l;
         self
     }
     fn dot(&self, b: &Vec3) -> f64 {
-        return self.x * b.x + self.y * b.y + self.z * b.z;
+        self.x * b.x + self.y * b.y + self.z * b.z
     }
 }
 
@@ -86,93 +124,37 @@ impl Ray {
     }
 }
 
-enum Refl {
-    Diff,
-    Spec,
-    Refr,
-}
-
-struct Sphere {
-    rad: f64,
-    p: Vec3,
-    e: Vec3,
-    c: Vec3,
-    refl: Refl,
-}
-
-impl Sphere {
-    pub fn intersect(&self, ray: &Ray) -> Option<f64> {
-        let po = self.p - ray.o;
-        let b = po.dot(&ray.d);
-        let d4 = b * b - po.dot(&po) + self.rad * self.rad;
-
-        if d4 < 0.0 {
-            return None;
-        }
-
-        let sqrt_d4 = d4.sqrt();
-        let t1 = b - sqrt_d4;
-        let t2 = b + sqrt_d4;
-
-        if t1 < EPS && t2 < EPS {
-            return None;
-        }
-
-        if t1 > EPS {
-            return Some(t1);
-        } else {
-            return Some(t2);
-        }
-    }
-}
-
-lazy_static! {
-    static ref SPHERES: [Sphere; 9] = [
-        Sphere { rad: 1e5,   p: Vec3::new( 1e5 + 1.0,      40.8, 81.6),e: Vec3::zero(),               c: Vec3::new(0.75, 0.25, 0.25), refl: Refl::Diff },//left
-        Sphere { rad: 1e5,   p: Vec3::new(-1e5 + 99.0,    40.8, 81.6),e: Vec3::zero(),                c: Vec3::new(0.25, 0.25, 0.75), refl: Refl::Diff },//right
-        Sphere { rad: 1e5,   p: Vec3::new(50.0,            40.8, 1e5),e: Vec3::zero(),                c: Vec3::new(0.75, 0.75, 0.75), refl: Refl::Diff },//front
-        Sphere { rad: 1e5,   p: Vec3::new(50.0,    40.8,-1e5 + 170.0),e: Vec3::zero(),                c: Vec3::zero(), refl: Refl::Diff },//back
-        Sphere { rad: 1e5,   p: Vec3::new(50.0,            1e5, 81.6),e: Vec3::zero(),                c: Vec3::new(0.75, 0.75, 0.75), refl: Refl::Diff },//bottom
-        Sphere { rad: 1e5,   p: Vec3::new(50.0,-1e5 + 81.6+4.0, 81.6),e: Vec3::zero(),                c: Vec3::new(0.75, 0.75, 0.75), refl: Refl::Diff },//top
-        Sphere { rad: 16.5,  p: Vec3::new(27.0,           16.5, 47.0),e: Vec3::zero(),                c: Vec3::new(1.0, 1.0, 1.0) * 0.999, refl: Refl::Spec },
-        Sphere { rad: 16.5,  p: Vec3::new(73.0,           16.5, 78.0),e: Vec3::zero(),                c: Vec3::new(1.0, 1.0, 1.0) * 0.999, refl: Refl::Refr },
-        Sphere { rad: 600.0, p: Vec3::new(50.0, 681.6-0.27+4.0, 81.6),e: Vec3::new(12.0, 12.0, 12.0), c: Vec3::zero(), refl: Refl::Diff },
-    ];
-}
-
 type Color = Vec3;
 
 fn clamp(x: f64) -> f64 {
-    if x < 0.0 {
-        0.0
-    } else if x > 1.0 {
-        1.0
-    } else {
-        x
-    }
+    x.clamp(0.0, 1.0)
 }
 
 fn to_int(x: f64) -> u8 {
     (clamp(x).powf(1.0 / 2.2) * 255.0 + 0.5) as u8
 }
 
-#[allow(dead_code)]
-fn save_ppm_file(filename: &str, image: Vec<Color>, width: usize, height: usize) {
-    let mut f = fs::File::create(filename).unwrap();
-    writeln!(f, "P3\n{} {}\n{}", width, height, 255).unwrap();
-    for i in 0..(width * (height)) {
-        write!(
-            f,
-            "{} {} {} ",
-            to_int(image[i as usize].x),
-            to_int(image[i as usize].y),
-            to_int(image[i as usize].z)
-        )
-        .unwrap();
+fn save_ppm_file(
+    filename: &str,
+    image: &[Color],
+    width: usize,
+    height: usize,
+) -> std::io::Result<()> {
+    let mut f = std::io::BufWriter::new(fs::File::create(filename)?);
+    writeln!(f, "P3\n{} {}\n{}", width, height, 255)?;
+    for c in image.iter().take(width * height) {
+        write!(f, "{} {} {} ", to_int(c.x), to_int(c.y), to_int(c.z))?;
     }
+    f.flush()
 }
 
-fn save_png_file(filename: &str, out_image: Vec<Color>, width: usize, height: usize) {
+fn save_image_file(
+    filename: &str,
+    out_image: &[Color],
+    width: usize,
+    height: usize,
+    format: image::ImageFormat,
+) -> image::ImageResult<()> {
     // Create a new ImgBuf with width: imgx and height: imgy
     let mut imgbuf = image::ImageBuffer::new(width as u32, height as u32);
 
@@ -185,143 +167,325 @@ fn save_png_file(filename: &str, out_image: Vec<Color>, width: usize, height: us
         *pixel = image::Rgb([r, g, b]);
     }
 
-    // Save the image t is deduced from the path
-    imgbuf.save(filename).unwrap();
+    imgbuf.save_with_format(filename, format)
 }
 
-fn intersect(r: &Ray, t: &mut f64, id: &mut usize) -> bool {
-    *t = INF;
-    for i in 0..SPHERES.len() {
-        match SPHERES[i].intersect(r) {
-            Some(d) => {
-                if d < *t {
-                    *t = d;
-                    *id = i;
-                }
-            }
-            None => {}
-        }
+fn save_file(
+    filename: &str,
+    format: &str,
+    image: &[Color],
+    w: usize,
+    h: usize,
+) -> Result<(), String> {
+    if format == "ppm" {
+        return save_ppm_file(filename, image, w, h).map_err(|e| e.to_string());
+    }
+    match image::ImageFormat::from_extension(format) {
+        Some(f) => save_image_file(filename, image, w, h, f).map_err(|e| e.to_string()),
+        None => Err(format!("unsupported output format '{}'", format)),
+    }
+}
+
+// Power heuristic weight of a sample taken with density a, where another
+// strategy would have taken it with density b.
+fn mis(a: f64, b: f64) -> f64 {
+    a * a / (a * a + b * b)
+}
+
+// Light reflected by mat at x facing nl towards -d from the environment and
+// from one of the sampled lights, with a shadow ray each through medium,
+// the one at x. Each is weighted against mat picking the same direction.
+fn direct(
+    scene: &Scene,
+    mat: &dyn Material,
+    d: Vec3,
+    x: Vec3,
+    nl: Vec3,
+    medium: Option<&Medium>,
+) -> Vec3 {
+    let l = direct_light(scene, mat, d, x, nl, medium);
+    match &scene.env {
+        Some(env) => l + direct_env(scene, &**env, mat, d, x, nl, medium),
+        None => l,
     }
-    return *t < INF;
 }
 
-fn radiance(r: &Ray, depth: u8) -> Vec3 {
-    let mut t: f64 = 0.0;
-    let mut id = 0;
-    if !intersect(r, &mut t, &mut id) {
+fn direct_env(
+    scene: &Scene,
+    env: &dyn Environment,
+    mat: &dyn Material,
+    d: Vec3,
+    x: Vec3,
+    nl: Vec3,
+    medium: Option<&Medium>,
+) -> Vec3 {
+    let (wi, pdf) = match env.sample() {
+        Some(s) => s,
+        None => return Vec3::zero(),
+    };
+    let f = mat.eval(d, wi, nl);
+    if f.dot(&f) == 0.0 {
         return Vec3::zero();
     }
-    let obj = &SPHERES[id];
-    let x = r.o + r.d * t;
-    let n = (x - obj.p).norm();
-    let nl = if n.dot(&r.d) < 0.0 { n } else { n * -1.0 };
-    let mut f = obj.c;
-    let p = if f.x > f.y && f.x > f.z {
-        f.x
-    } else if f.y > f.z {
-        f.y
-    } else {
-        f.z
+    let tr = transmittance(scene, x, wi, INF, medium);
+    let w = mis(pdf, mat.pdf(d, wi, nl)) / pdf;
+    env.radiance(wi).mult(&f).mult(&tr) * w
+}
+
+// one light picked by Scene::pick_light()
+fn direct_light(
+    scene: &Scene,
+    mat: &dyn Material,
+    d: Vec3,
+    x: Vec3,
+    nl: Vec3,
+    medium: Option<&Medium>,
+) -> Vec3 {
+    let light = match scene.pick_light() {
+        Some(l) => l,
+        None => return Vec3::zero(),
     };
-    let depth = depth + 1;
-    if depth > 5 {
-        if depth < 127 && random() < p {
-            f = f * (1.0 / p);
-        } else {
-            return obj.e;
+    let obj = &*scene.objects[light.obj];
+    let (wi, pdf, t) = match light.sample(obj, x) {
+        Some(s) => s,
+        None => return Vec3::zero(),
+    };
+    let f = mat.eval(d, wi, nl);
+    if f.dot(&f) == 0.0 {
+        return Vec3::zero();
+    }
+    let tr = transmittance(scene, x, wi, t, medium);
+    match obj.material() {
+        Some(m) => {
+            let pdf = pdf * scene.light_prob(light);
+            let w = mis(pdf, mat.pdf(d, wi, nl)) / pdf;
+            scene.materials[m].emitted().mult(&f).mult(&tr) * w
         }
+        None => Vec3::zero(),
     }
+}
 
-    return match obj.refl {
-        Refl::Diff => {
-            let r1 = 2.0 * std::f64::consts::PI * random();
-            let r2 = random();
-            let r2s = r2.sqrt();
-            let w = nl;
-            let u = ((if w.x.abs() > 0.1 {
-                Vec3::new(0.0, 1.0, 0.0)
-            } else {
-                Vec3::new(1.0, 0.0, 0.0)
-            }) % w)
-                .norm();
-            let v = w % u;
-            let d =
-                (u * f64::cos(r1) * r2s + v * f64::sin(r1) * r2s + w * (1.0 - r2).sqrt()).norm();
-            obj.e + f.mult(&radiance(&Ray::new(x, d), depth))
+// Of the light leaving distance t along wi from x, what gets there through
+// the media on the way and the clear edges of volumes; nothing if anything
+// else is in the way, or too many edges. medium is the one at x.
+fn transmittance<'a>(
+    scene: &'a Scene,
+    mut x: Vec3,
+    wi: Vec3,
+    mut t: f64,
+    mut medium: Option<&'a Medium>,
+) -> Vec3 {
+    let mut tr = Vec3::new(1.0, 1.0, 1.0);
+    for _ in 0..=MAX_CROSSINGS {
+        let hit = scene.intersect(&Ray::new(x, wi), t - EPS);
+        if let Some(m) = medium {
+            tr = tr.mult(&m.transmittance(hit.as_ref().map_or(t, |h| h.t)));
         }
-        Refl::Spec => obj.e + f.mult(&radiance(&Ray::new(x, r.d - n * 2.0 * n.dot(&r.d)), depth)),
-        _ => {
-            // Refl.Refr
-            let refl_ray = Ray::new(x, r.d - n * 2.0 * n.dot(&r.d));
-            let into = n.dot(&nl) > 0.0;
-            let nc = 1.0;
-            let nt = 1.5;
-            let nnt = if into { nc / nt } else { nt / nc };
-            let ddn = r.d.dot(&nl);
-            let cos2t = 1.0 - nnt * nnt * (1.0 - ddn * ddn);
-            if cos2t < 0.0 {
-                obj.e + f.mult(&radiance(&refl_ray, depth))
-            } else {
-                let tdir =
-                    r.d * nnt - n * ((if into { 1.0 } else { -1.0 }) * (ddn * nnt + cos2t.sqrt()));
-                tdir.norm();
-                let a = nt - nc;
-                let b = nt + nc;
-                let r0 = a * a / (b * b);
-                let c = 1.0 - (if into { -ddn } else { tdir.dot(&n) });
-                let re = r0 + (1.0 - r0) * c * c * c * c * c;
-                let tr = 1.0 - re;
-                let p = 0.25 + 0.5 * re;
-                let rp = re / p;
-                let tp = tr / (1.0 - p);
-                obj.e
-                    + f.mult(
-                        &(if depth > 2 {
-                            if random() < p {
-                                radiance(&refl_ray, depth) * rp
-                            } else {
-                                radiance(&Ray::new(x, tdir), depth) * tp
-                            }
-                        } else {
-                            radiance(&refl_ray, depth) * re
-                                + radiance(&Ray::new(x, tdir), depth) * tr
-                        }),
-                    )
-            }
+        let hit = match hit {
+            Some(h) => h,
+            None => return tr,
+        };
+        let mat = &*scene.materials[hit.mat];
+        if !mat.clear() {
+            return Vec3::zero();
         }
-    };
+        medium = if hit.n.dot(&wi) < 0.0 {
+            mat.medium()
+        } else {
+            scene.fog.as_ref()
+        };
+        x = hit.p;
+        t -= hit.t;
+    }
+    Vec3::zero()
 }
 
-fn main() {
-    let w: usize = 640;
-    let h: usize = 480;
-    let samps = if std::env::args().len() == 2 {
-        std::env::args().skip(1).next().unwrap().parse().unwrap()
+// Past rr_depth, ends the path with a chance that grows as f dims, and makes
+// up for it in the paths that go on.
+fn roulette(f: Vec3, depth: usize, rr_depth: usize) -> Option<Vec3> {
+    if depth <= rr_depth {
+        return Some(f);
+    }
+    let p = f.x.max(f.y).max(f.z).min(1.0);
+    if random() < p {
+        Some(f * (1.0 / p))
     } else {
-        1
-    };
-    let cam = Ray::new(
-        Vec3::new(50.0, 52.0, 295.6),
-        Vec3::new(0.0, -0.042612, -1.0).norm(),
-    );
+        None
+    }
+}
 
-    let cx = Vec3::new((w as f64) * 0.5135 / (h as f64), 0.0, 0.0);
-    let cy = (cx % cam.d).norm() * 0.5135;
-    let mut image = vec![Color::zero(); (w * h) as usize];
+// Density with which direct() would have picked r from its origin towards
+// the sampled light it hit. Lights sharing a material are told apart by
+// their distance.
+fn light_pdf(scene: &Scene, r: &Ray, hit: &Hit) -> f64 {
+    scene
+        .lights
+        .iter()
+        .find(|l| {
+            let o = &scene.objects[l.obj];
+            o.material() == Some(hit.mat)
+                && o.intersect(r, INF)
+                    .is_some_and(|h| (h.t - hit.t).abs() < EPS)
+        })
+        .map_or(0.0, |l| {
+            l.pdf(&*scene.objects[l.obj], r, hit) * scene.light_prob(l)
+        })
+}
 
-    let bands: Vec<(usize, &mut [Color])> = image.chunks_mut(w as usize).enumerate().collect();
+// Follows one path from the camera ray, keeping the product of the
+// reflectances along it in throughput.
+fn radiance(scene: &Scene, ray: &Ray) -> Vec3 {
+    let Settings {
+        max_depth,
+        rr_depth,
+        ..
+    } = scene.settings;
+    let mut r = Ray::new(ray.o, ray.d);
+    let mut l = Vec3::zero();
+    let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+    // density of the diffuse bounce that chose r, so that light found by it
+    // can be weighted against direct(); None for camera rays and mirror or
+    // glass bounces
+    let mut pdf: Option<f64> = None;
+    // what the path is travelling through; media don't nest, so leaving
+    // anything goes back to the fog
+    let mut medium = scene.fog.as_ref();
+    // where the path last scattered
+    let mut from = r.o;
+    let mut depth = 1;
+    let mut crossings = 0;
+    loop {
+        let hit = scene.intersect(&r, INF);
+        // in a medium the path may scatter before it gets to the hit
+        let (scattered, w) = match medium {
+            Some(m) => m.sample(hit.as_ref().map_or(INF, |h| h.t)),
+            None => (None, Vec3::new(1.0, 1.0, 1.0)),
+        };
+        if let (Some(t), Some(m)) = (scattered, medium) {
+            if depth >= max_depth {
+                break;
+            }
+            let x = r.o + r.d * t;
+            let phase = Phase { g: m.g };
+            let lit = direct(scene, &phase, r.d, x, r.d, medium);
+            l = l + throughput.mult(&w).mult(&lit);
+            let s = match phase.sample(r.d, r.d, r.d) {
+                Some(s) => s,
+                None => break,
+            };
+            throughput = match roulette(w.mult(&s.weight), depth, rr_depth) {
+                Some(f) => throughput.mult(&f),
+                None => break,
+            };
+            pdf = s.pdf;
+            from = x;
+            r = Ray::new(x, s.d);
+            depth += 1;
+            continue;
+        }
+        throughput = throughput.mult(&w);
+        let hit = match hit {
+            Some(hit) => hit,
+            None => {
+                if let Some(env) = &scene.env {
+                    let w = pdf.map_or(1.0, |pdf| mis(pdf, env.pdf(r.d)));
+                    l = l + throughput.mult(&env.radiance(r.d)) * w;
+                }
+                break;
+            }
+        };
+        let mat = &*scene.materials[hit.mat];
+        let (x, n) = (hit.p, hit.n);
+        // Straight on into the volume or out of it. This is no bounce, so
+        // light found beyond it is weighted as before it.
+        if mat.clear() {
+            crossings += 1;
+            if crossings > MAX_CROSSINGS {
+                break;
+            }
+            medium = if r.d.dot(&n) < 0.0 {
+                mat.medium()
+            } else {
+                scene.fog.as_ref()
+            };
+            r = Ray::new(x, r.d);
+            continue;
+        }
+        let nl = if n.dot(&r.d) < 0.0 { n } else { n * -1.0 };
+        // from inside, the ray has crossed the material
+        let a = mat.absorption();
+        if n.dot(&r.d) > 0.0 && a.dot(&a) > 0.0 {
+            let t = a * -hit.t;
+            throughput = throughput.mult(&Vec3::new(t.x.exp(), t.y.exp(), t.z.exp()));
+        }
+        let e = match pdf {
+            Some(pdf) if scene.sampled(hit.mat) => {
+                // from where the path last scattered, through any clear edges
+                let back = Ray::new(from, r.d);
+                let t = (hit.p - from).dot(&r.d);
+                mat.emitted() * mis(pdf, light_pdf(scene, &back, &Hit { t, ..hit }))
+            }
+            _ => mat.emitted(),
+        };
+        l = l + throughput.mult(&e);
+        if depth >= max_depth {
+            break;
+        }
+
+        let s = match mat.sample(r.d, n, nl) {
+            Some(s) => s,
+            None => break,
+        };
+        let tint = mat.tint(hit.uv);
+        if s.pdf.is_some() {
+            let lit = direct(scene, mat, r.d, x, nl, medium);
+            l = l + throughput.mult(&lit).mult(&tint);
+        }
+        throughput = match roulette(s.weight.mult(&tint), depth, rr_depth) {
+            Some(f) => throughput.mult(&f),
+            None => break,
+        };
+        // through the surface, into what is behind it
+        if s.d.dot(&n) * r.d.dot(&n) > 0.0 {
+            medium = if r.d.dot(&n) < 0.0 {
+                mat.medium()
+            } else {
+                scene.fog.as_ref()
+            };
+        }
+        pdf = s.pdf;
+        from = x;
+        r = Ray::new(x, s.d);
+        depth += 1;
+    }
+    l
+}
+
+fn render(scene: &Scene, progress: bool) -> Vec<Color> {
+    let Settings {
+        w, h, samps, seed, ..
+    } = scene.settings;
+    let cam = &scene.cam;
+    let basis = cam.basis(w, h);
+    let mut image = vec![Color::zero(); w * h];
+
+    let bands: Vec<(usize, &mut [Color])> = image.chunks_mut(w).enumerate().collect();
     bands.into_par_iter().for_each(|(y, band)| {
-        let y2 = h - (y as usize) - 1;
-        if (y % 10) == 0 {
-            writeln!(
-                std::io::stderr(),
+        let y2 = h - y - 1;
+        if let Some(seed) = seed {
+            seed_random(
+                seed.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+                    .wrapping_add(y as u64),
+            );
+        }
+        if progress && (y % 10) == 0 {
+            eprintln!(
                 "Rendering ({} spp) {:5.2}%",
                 samps * 4,
                 100.0 * (y as f64) / ((h as f64) - 1.0)
-            )
-            .unwrap();
+            );
         }
-        for x in 0..w {
+        for (x, px) in band.iter_mut().enumerate() {
             let mut r = Vec3::zero();
             for sy in 0..2 {
                 for sx in 0..2 {
@@ -338,21 +502,313 @@ fn main() {
                         } else {
                             1.0 - (2.0 - r2).sqrt()
                         };
-                        let d = cx
-                            * ((((sx as f64) + 0.5 + dx) / 2.0 + (x as f64)) / (w as f64) - 0.5)
-                            + cy * ((((sy as f64) + 0.5 + dy) / 2.0 + (y2 as f64)) / (h as f64)
-                                - 0.5)
-                            + cam.d;
-                        r = r + radiance(&(Ray::new(cam.o + d * 140.0, d.norm())), 0)
-                            * (1.0 / (samps as f64));
+                        let u = (((sx as f64) + 0.5 + dx) / 2.0 + (x as f64)) / (w as f64) - 0.5;
+                        let v = (((sy as f64) + 0.5 + dy) / 2.0 + (y2 as f64)) / (h as f64) - 0.5;
+                        if let Some(ray) = cam.ray(&basis, u, v) {
+                            r = r + radiance(scene, &ray) * (1.0 / (samps as f64));
+                        }
                     }
-                    band[x as usize] = band[x as usize] + r * (1.0 / 4.0 as f64);
+                    *px = *px + r * (1.0 / 4.0);
                     r = Vec3::zero();
                 }
             }
         }
     });
+    image
+}
+
+// With --weigh-lights; with --seed too, the same weights every time.
+fn weigh_lights(scene: &mut Scene) {
+    if !scene.settings.weigh_lights {
+        return;
+    }
+    if let Some(seed) = scene.settings.seed {
+        seed_random(seed);
+    }
+    scene.weigh_lights();
+}
+
+// Renders the scene by brute force and with the BVH, with the same seed so
+// the images can be compared.
+fn bench(scene: &mut Scene) {
+    scene.settings.seed = scene.settings.seed.or(Some(0));
+    weigh_lights(scene);
+    let start = Instant::now();
+    let brute = render(scene, false);
+    let t_brute = start.elapsed().as_secs_f64();
+    println!("brute force: {:.3}s", t_brute);
+
+    let start = Instant::now();
+    scene.build_bvh();
+    let t_build = start.elapsed().as_secs_f64();
+    let image = render(scene, false);
+    let t_bvh = start.elapsed().as_secs_f64();
+    let (prims, nodes) = scene.bvh_size().unwrap();
+    println!(
+        "bvh:         {:.3}s ({} primitives, {} nodes, built in {:.3}s), {:.1}x faster",
+        t_bvh,
+        prims,
+        nodes,
+        t_build,
+        t_brute / t_bvh
+    );
+    let same = brute
+        .iter()
+        .zip(&image)
+        .all(|(a, b)| a.x == b.x && a.y == b.y && a.z == b.z);
+    println!("images {}", if same { "match" } else { "differ" });
+}
+
+fn main() {
+    let opt = match cli::parse(std::env::args().skip(1)) {
+        Ok(opt) => opt,
+        Err(e) => {
+            eprintln!("error: {}\n(try --help)", e);
+            std::process::exit(2);
+        }
+    };
+    if opt.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    if opt.list {
+        for name in scenes::names() {
+            println!("{}", name);
+        }
+        return;
+    }
+
+    let mut scene = match &opt.file {
+        Some(path) => match scenefile::load(path) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let scene_name = opt.scene.as_deref().unwrap_or("debug");
+            match scenes::find(scene_name) {
+                Some(def) => (def.build)(),
+                None => {
+                    eprintln!(
+                        "error: unknown scene '{}' (available: {})",
+                        scene_name,
+                        scenes::names().join(", ")
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+    let s = &mut scene.settings;
+    s.w = opt.w.unwrap_or(s.w);
+    s.h = opt.h.unwrap_or(s.h);
+    s.samps = opt.samps.unwrap_or(s.samps);
+    s.max_depth = opt.max_depth.unwrap_or(s.max_depth);
+    s.rr_depth = opt.rr_depth.unwrap_or(s.rr_depth);
+    s.seed = opt.seed.or(s.seed);
+    s.weigh_lights = opt.weigh_lights;
+    if let Some(p) = opt.projection {
+        scene.cam.projection = p;
+    }
+
+    if let Some(path) = &opt.export {
+        if let Err(e) = scenefile::save(&scene, path) {
+            eprintln!("error: {}: {}", path, e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    //    save_ppm_file("image.ppm", image, w, h);
-    save_png_file("image.png", image, w, h);
+    let format = match &opt.format {
+        Some(f) => f.to_lowercase(),
+        None => match std::path::Path::new(&opt.output).extension() {
+            Some(ext) => ext.to_string_lossy().to_lowercase(),
+            None => "png".to_string(),
+        },
+    };
+    if format != "ppm" && image::ImageFormat::from_extension(&format).is_none() {
+        eprintln!("error: unsupported output format '{}'", format);
+        std::process::exit(2);
+    }
+
+    if let Some(n) = opt.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build_global()
+            .unwrap();
+    }
+    let Settings { w, h, samps, .. } = scene.settings;
+    if opt.verbosity >= Verbosity::Verbose {
+        eprintln!(
+            "{}x{}, {} spp, {} objects ({} emissive, {} sampled), {} threads",
+            w,
+            h,
+            samps * 4,
+            scene.objects.len(),
+            scene.emissive.len(),
+            scene.lights.len(),
+            rayon::current_num_threads()
+        );
+    }
+    if opt.bench {
+        bench(&mut scene);
+        return;
+    }
+    let start = Instant::now();
+    if scene.prims() >= scene::BVH_MIN_PRIMS {
+        scene.build_bvh();
+    }
+    if opt.verbosity >= Verbosity::Verbose {
+        match scene.bvh_size() {
+            Some((prims, nodes)) => eprintln!(
+                "bvh: {} primitives, {} nodes, built in {:.3}s",
+                prims,
+                nodes,
+                start.elapsed().as_secs_f64()
+            ),
+            None => eprintln!("no bvh for {} primitives", scene.prims()),
+        }
+    }
+    weigh_lights(&mut scene);
+    if opt.verbosity >= Verbosity::Verbose {
+        for l in &scene.lights {
+            eprintln!(
+                "light: object {}, power {:.4e}, picked {:.1}%",
+                l.obj,
+                l.power,
+                100.0 * scene.light_prob(l)
+            );
+        }
+    }
+    let start = Instant::now();
+    let image = render(&scene, opt.verbosity >= Verbosity::Normal);
+    if opt.verbosity >= Verbosity::Verbose {
+        eprintln!("rendered in {:.2}s", start.elapsed().as_secs_f64());
+    }
+
+    if let Err(e) = save_file(&opt.output, &format, &image, w, h) {
+        eprintln!("error: {}: {}", opt.output, e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use material::Refl;
+
+    #[test]
+    fn mis_weights() {
+        for (a, b) in [(1.0, 1.0), (0.3, 7.0), (1e-3, 2.5), (4.0, 0.0)] {
+            assert!((mis(a, b) + mis(b, a) - 1.0).abs() < 1e-12);
+        }
+        assert_eq!(mis(4.0, 0.0), 1.0);
+        assert!((mis(1.0, 2.0) - 0.2).abs() < 1e-12);
+    }
+
+    // A grey floor under two spheres of different sizes and brightness.
+    fn floor() -> Scene {
+        let mut s = Scene::default();
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let grey = Vec3::new(0.5, 0.5, 0.5);
+        s.plane(Vec3::zero(), up, Vec3::zero(), grey, Refl::Diff);
+        let e = Vec3::new(4.0, 4.0, 4.0);
+        s.sphere(1.0, Vec3::new(0.0, 5.0, 0.0), e, Vec3::zero(), Refl::Diff);
+        s.sphere(
+            2.0,
+            Vec3::new(6.0, 8.0, 0.0),
+            e * 0.5,
+            Vec3::zero(),
+            Refl::Diff,
+        );
+        s
+    }
+
+    // Where a light sample from x ends on that light, light_pdf() of the
+    // hit gives the density direct_light() weighed it with.
+    #[test]
+    fn light_pdf_matches_sample() {
+        seed_random(10);
+        let scene = floor();
+        let x = Vec3::new(1.0, 0.0, 0.5);
+        let nl = Vec3::new(0.0, 1.0, 0.0);
+        let mat = &*scene.materials[0];
+        for _ in 0..2000 {
+            let light = scene.pick_light().unwrap();
+            let obj = &*scene.objects[light.obj];
+            let (wi, pdf, _) = light.sample(obj, x).unwrap();
+            let r = Ray::new(x, wi);
+            let hit = scene.intersect(&r, INF).unwrap();
+            assert_eq!(Some(hit.mat), obj.material());
+            let want = pdf * scene.light_prob(light);
+            let got = light_pdf(&scene, &r, &hit);
+            assert!((got - want).abs() < 1e-9 * want, "{} {}", got, want);
+            let b = mat.pdf(Vec3::new(0.0, -1.0, 0.0), wi, nl);
+            assert!((mis(want, b) + mis(b, got) - 1.0).abs() < 1e-9);
+        }
+        // nothing for what isn't a light
+        let r = Ray::new(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = scene.intersect(&r, INF).unwrap();
+        assert_eq!(light_pdf(&scene, &r, &hit), 0.0);
+    }
+
+    // Light sampling and bounces weighted against each other add up to the
+    // light the floor gets: a sphere of radius R at distance D whose centre is
+    // at angle a from the normal gives pi L (R/D)^2 cos a.
+    #[test]
+    fn weights_add_up() {
+        seed_random(11);
+        let scene = floor();
+        let (x, n) = (Vec3::new(1.0, 0.0, 0.5), Vec3::new(0.0, 1.0, 0.0));
+        let d = Vec3::new(0.0, -1.0, 0.0);
+        let mat = &*scene.materials[0];
+        let mut want = 0.0;
+        for (rad, c, e) in [
+            (1.0, Vec3::new(0.0, 5.0, 0.0), 4.0),
+            (2.0, Vec3::new(6.0, 8.0, 0.0), 2.0),
+        ] {
+            let v = c - x;
+            let d2 = v.dot(&v);
+            // times the grey floor's 0.5 / pi
+            want += 0.5 * e * rad * rad / d2 * v.norm().dot(&n);
+        }
+        let k = 200000;
+        let mut got = 0.0;
+        for _ in 0..k {
+            got += direct_light(&scene, mat, d, x, n, None).x;
+            let s = mat.sample(d, n, n).unwrap();
+            let r = Ray::new(x, s.d);
+            if let Some(hit) = scene.intersect(&r, INF) {
+                let e = scene.materials[hit.mat].emitted();
+                got += e.x * s.weight.x * mis(s.pdf.unwrap(), light_pdf(&scene, &r, &hit));
+            }
+        }
+        got /= k as f64;
+        assert!((got - want).abs() < 0.01 * want, "{} {}", got, want);
+    }
+
+    // Light through an absorbing ball that bends nothing falls off as
+    // exp(-a d) over the distance d inside it.
+    #[test]
+    fn beer() {
+        seed_random(18);
+        let mut scene = Scene::default();
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let a = Vec3::new(0.01, 0.05, 0.1);
+        let ball = scene.material(material::Glass::new(Vec3::zero(), white, 1.0, a));
+        scene.add(shape::Sphere::new(10.0, Vec3::zero(), ball));
+        scene.sphere(1000.0, Vec3::zero(), white, Vec3::zero(), Refl::Diff);
+        let r = Ray::new(Vec3::new(0.0, 0.0, -50.0), Vec3::new(0.0, 0.0, 1.0));
+        let n = 20000;
+        let mut l = Vec3::zero();
+        for _ in 0..n {
+            l = l + radiance(&scene, &r) * (1.0 / n as f64);
+        }
+        for (got, a) in [(l.x, a.x), (l.y, a.y), (l.z, a.z)] {
+            let want = (-20.0 * a).exp();
+            assert!((got - want).abs() < 0.03 * want, "{} {}", got, want);
+        }
+    }
 }
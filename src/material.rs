@@ -0,0 +1,351 @@
+use crate::medium::Medium;
+use crate::scenefile::vec_str;
+use crate::shape::basis;
+use crate::texture::Texture;
+use crate::{random, Vec3};
+use std::f64::consts::{FRAC_1_PI, PI};
+use std::sync::Arc;
+
+// indices of refraction
+pub const GLASS: f64 = 1.5;
+pub const WATER: f64 = 1.33;
+pub const DIAMOND: f64 = 2.42;
+
+// The smallpt reflection types, for the scene shorthands.
+pub enum Refl {
+    Diff,
+    Spec,
+    Refr,
+}
+
+// Where a path goes from a surface and what the surface gives off. d is the
+// direction the path arrived in, n the outward normal and nl the normal on
+// the side d came from; directions picked are away from the surface.
+pub trait Material: Send + Sync {
+    fn sample(&self, d: Vec3, n: Vec3, nl: Vec3) -> Option<Sample>;
+    // BSDF times the cosine at wi; zero for smooth surfaces, which only
+    // sample() can find light through
+    fn eval(&self, _d: Vec3, _wi: Vec3, _nl: Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+    // density with which sample() picks wi
+    fn pdf(&self, _d: Vec3, _wi: Vec3, _nl: Vec3) -> f64 {
+        0.0
+    }
+    fn emitted(&self) -> Vec3;
+    // what sample() and eval() are multiplied by at surface coordinates uv
+    fn tint(&self, _uv: (f64, f64)) -> Vec3 {
+        Vec3::new(1.0, 1.0, 1.0)
+    }
+    // of light travelling inside, per unit length (Beer's law)
+    fn absorption(&self) -> Vec3 {
+        Vec3::zero()
+    }
+    // what fills the inside, if light scatters there
+    fn medium(&self) -> Option<&Medium> {
+        None
+    }
+    // whether this is no surface at all, only the edge of a medium
+    fn clear(&self) -> bool {
+        false
+    }
+    // scene file fields
+    fn export(&self) -> String;
+}
+
+pub struct Sample {
+    pub d: Vec3,
+    // eval() over pdf()
+    pub weight: Vec3,
+    // None for smooth surfaces, where light found through d is not weighted
+    // against sampling lights
+    pub pdf: Option<f64>,
+}
+
+pub fn from_refl(e: Vec3, c: Vec3, refl: Refl) -> Box<dyn Material> {
+    match refl {
+        Refl::Diff => Box::new(Diffuse::new(e, c)),
+        Refl::Spec => Box::new(Mirror::new(e, c)),
+        Refl::Refr => Box::new(Glass::new(e, c, GLASS, Vec3::zero())),
+    }
+}
+
+pub fn fields(e: Vec3, c: Vec3, refl: &str) -> String {
+    format!("e={} c={} refl={}", vec_str(e), vec_str(c), refl)
+}
+
+fn reflect(d: Vec3, n: Vec3) -> Vec3 {
+    d - n * 2.0 * n.dot(&d)
+}
+
+pub struct Diffuse {
+    pub e: Vec3,
+    pub c: Vec3,
+}
+
+impl Diffuse {
+    pub fn new(e: Vec3, c: Vec3) -> Diffuse {
+        Diffuse { e, c }
+    }
+}
+
+impl Material for Diffuse {
+    // cosine weighted
+    fn sample(&self, _d: Vec3, _n: Vec3, nl: Vec3) -> Option<Sample> {
+        let r1 = 2.0 * PI * random();
+        let r2 = random();
+        let r2s = r2.sqrt();
+        let (u, v) = basis(nl);
+        let d = (u * f64::cos(r1) * r2s + v * f64::sin(r1) * r2s + nl * (1.0 - r2).sqrt()).norm();
+        Some(Sample {
+            d,
+            weight: self.c,
+            pdf: Some(d.dot(&nl) * FRAC_1_PI),
+        })
+    }
+
+    fn eval(&self, _d: Vec3, wi: Vec3, nl: Vec3) -> Vec3 {
+        self.c * (wi.dot(&nl).max(0.0) * FRAC_1_PI)
+    }
+
+    fn pdf(&self, _d: Vec3, wi: Vec3, nl: Vec3) -> f64 {
+        wi.dot(&nl).max(0.0) * FRAC_1_PI
+    }
+
+    fn emitted(&self) -> Vec3 {
+        self.e
+    }
+
+    fn export(&self) -> String {
+        fields(self.e, self.c, "diff")
+    }
+}
+
+pub struct Mirror {
+    pub e: Vec3,
+    pub c: Vec3,
+}
+
+impl Mirror {
+    pub fn new(e: Vec3, c: Vec3) -> Mirror {
+        Mirror { e, c }
+    }
+}
+
+impl Material for Mirror {
+    fn sample(&self, d: Vec3, n: Vec3, _nl: Vec3) -> Option<Sample> {
+        Some(Sample {
+            d: reflect(d, n),
+            weight: self.c,
+            pdf: None,
+        })
+    }
+
+    fn emitted(&self) -> Vec3 {
+        self.e
+    }
+
+    fn export(&self) -> String {
+        fields(self.e, self.c, "spec")
+    }
+}
+
+// Smooth dielectric with index ior, in air.
+pub struct Glass {
+    pub e: Vec3,
+    pub c: Vec3,
+    pub ior: f64,
+    pub absorb: Vec3,
+}
+
+impl Glass {
+    pub fn new(e: Vec3, c: Vec3, ior: f64, absorb: Vec3) -> Glass {
+        Glass { e, c, ior, absorb }
+    }
+}
+
+impl Material for Glass {
+    // reflection or refraction, picked with a bias towards reflection
+    fn sample(&self, d: Vec3, n: Vec3, nl: Vec3) -> Option<Sample> {
+        let refl = reflect(d, n);
+        let into = n.dot(&nl) > 0.0;
+        let nc = 1.0;
+        let nt = self.ior;
+        let nnt = if into { nc / nt } else { nt / nc };
+        let ddn = d.dot(&nl);
+        let cos2t = 1.0 - nnt * nnt * (1.0 - ddn * ddn);
+        if cos2t < 0.0 {
+            return Some(Sample {
+                d: refl,
+                weight: self.c,
+                pdf: None,
+            });
+        }
+        let tdir = d * nnt - n * ((if into { 1.0 } else { -1.0 }) * (ddn * nnt + cos2t.sqrt()));
+        let a = nt - nc;
+        let b = nt + nc;
+        let r0 = a * a / (b * b);
+        let c = 1.0 - (if into { -ddn } else { tdir.dot(&n) });
+        let re = r0 + (1.0 - r0) * c * c * c * c * c;
+        let tr = 1.0 - re;
+        let p = 0.25 + 0.5 * re;
+        let (d, w) = if random() < p {
+            (refl, re / p)
+        } else {
+            (tdir, tr / (1.0 - p))
+        };
+        Some(Sample {
+            d,
+            weight: self.c * w,
+            pdf: None,
+        })
+    }
+
+    fn emitted(&self) -> Vec3 {
+        self.e
+    }
+
+    fn absorption(&self) -> Vec3 {
+        self.absorb
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "{} ior={} absorb={}",
+            fields(self.e, self.c, "refr"),
+            self.ior,
+            vec_str(self.absorb)
+        )
+    }
+}
+
+// Another material with its colour multiplied by a texture.
+pub struct Textured {
+    pub mat: Box<dyn Material>,
+    pub tex: Arc<Texture>,
+}
+
+impl Textured {
+    pub fn new(mat: Box<dyn Material>, tex: Arc<Texture>) -> Textured {
+        Textured { mat, tex }
+    }
+}
+
+impl Material for Textured {
+    fn sample(&self, d: Vec3, n: Vec3, nl: Vec3) -> Option<Sample> {
+        self.mat.sample(d, n, nl)
+    }
+
+    fn eval(&self, d: Vec3, wi: Vec3, nl: Vec3) -> Vec3 {
+        self.mat.eval(d, wi, nl)
+    }
+
+    fn pdf(&self, d: Vec3, wi: Vec3, nl: Vec3) -> f64 {
+        self.mat.pdf(d, wi, nl)
+    }
+
+    fn emitted(&self) -> Vec3 {
+        self.mat.emitted()
+    }
+
+    fn tint(&self, uv: (f64, f64)) -> Vec3 {
+        self.tex.value(uv)
+    }
+
+    fn absorption(&self) -> Vec3 {
+        self.mat.absorption()
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        self.mat.medium()
+    }
+
+    fn clear(&self) -> bool {
+        self.mat.clear()
+    }
+
+    fn export(&self) -> String {
+        format!("{} tex={}", self.mat.export(), self.tex.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed_random;
+
+    fn up() -> Vec3 {
+        Vec3::new(0.0, 0.0, 1.0)
+    }
+
+    fn schlick(ior: f64, cos: f64) -> f64 {
+        let r0 = (ior - 1.0) * (ior - 1.0) / ((ior + 1.0) * (ior + 1.0));
+        r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+    }
+
+    // (reflected, refracted) weight per sample, and the sine of the refracted
+    // direction, for d arriving at theta degrees from the normal
+    fn split(g: &Glass, theta: f64, inside: bool) -> (f64, f64, f64) {
+        let (s, c) = theta.to_radians().sin_cos();
+        let (d, nl) = if inside {
+            (Vec3::new(s, 0.0, c), up() * -1.0)
+        } else {
+            (Vec3::new(s, 0.0, -c), up())
+        };
+        let n = 40000;
+        let (mut re, mut tr, mut sin) = (0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let smp = g.sample(d, up(), nl).unwrap();
+            assert!(smp.pdf.is_none());
+            if smp.d.z * d.z < 0.0 {
+                re += smp.weight.x / n as f64;
+                assert!((smp.d.x - s).abs() < 1e-12);
+            } else {
+                tr += smp.weight.x / n as f64;
+                sin = smp.d.x;
+            }
+        }
+        (re, tr, sin)
+    }
+
+    #[test]
+    fn fresnel() {
+        seed_random(16);
+        for ior in [GLASS, WATER, DIAMOND] {
+            let g = Glass::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0), ior, Vec3::zero());
+            for theta in [0.0, 40.0, 80.0] {
+                let (re, tr, sin) = split(&g, theta, false);
+                let want = schlick(ior, theta.to_radians().cos());
+                assert!((re - want).abs() < 0.02, "{} {} {}", ior, re, want);
+                assert!((tr - (1.0 - want)).abs() < 0.02, "{} {}", ior, tr);
+                // Snell's law
+                assert!((sin * ior - theta.to_radians().sin()).abs() < 1e-9);
+            }
+            // from inside, out through the surface below the critical angle
+            let critical = (1.0 / ior).asin().to_degrees();
+            let theta = 0.5 * critical;
+            let (re, tr, sin) = split(&g, theta, true);
+            let out = (theta.to_radians().sin() * ior).asin();
+            assert!((sin - out.sin()).abs() < 1e-9);
+            let want = schlick(ior, out.cos());
+            assert!((re - want).abs() < 0.02 && (tr - (1.0 - want)).abs() < 0.02);
+            // and all of it reflected beyond it
+            let (re, tr, _) = split(&g, critical + 1.0, true);
+            assert!((re - 1.0).abs() < 1e-9 && tr == 0.0);
+        }
+    }
+
+    #[test]
+    fn lossless() {
+        seed_random(17);
+        // a matched index lets light through unbent, and all of it head on
+        let g = Glass::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0), 1.0, Vec3::zero());
+        let (_, _, sin) = split(&g, 50.0, false);
+        assert!((sin - 50f64.to_radians().sin()).abs() < 1e-12);
+        let (re, tr, _) = split(&g, 0.0, false);
+        assert!(re == 0.0 && (tr - 1.0).abs() < 0.02);
+        let a = Vec3::new(0.1, 0.2, 0.3);
+        assert_eq!(Glass::new(Vec3::zero(), g.c, GLASS, a).absorption().z, 0.3);
+        assert_eq!(Diffuse::new(Vec3::zero(), g.c).absorption().x, 0.0);
+    }
+}
@@ -0,0 +1,336 @@
+// Homogeneous participating media: fog filling the scene, and volumes
+// filling closed shapes. Paths through them are sampled by free-flight
+// distance and scatter by the Henyey-Greenstein phase function.
+
+use crate::material::{Material, Sample};
+use crate::scenefile::vec_str;
+use crate::shape::basis;
+use crate::{random, Vec3};
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy)]
+pub struct Medium {
+    // of red, green and blue light, per unit length
+    pub absorb: Vec3,
+    pub scatter: Vec3,
+    // mean cosine of the scattering angle: 0 scatters evenly, towards 1
+    // mostly forwards, towards -1 back
+    pub g: f64,
+}
+
+impl Medium {
+    pub fn new(absorb: Vec3, scatter: Vec3, g: f64) -> Medium {
+        Medium { absorb, scatter, g }
+    }
+
+    fn extinction(&self) -> Vec3 {
+        self.absorb + self.scatter
+    }
+
+    // of light crossing distance t
+    pub fn transmittance(&self, t: f64) -> Vec3 {
+        let e = self.extinction() * -t;
+        Vec3::new(e.x.exp(), e.y.exp(), e.z.exp())
+    }
+
+    // How far a ray that meets a surface at t_max gets: Some(t) if it
+    // scatters there first, None if it reaches the surface, with the weight
+    // for either. The distance is sampled for red, green or blue at random
+    // and weighted by the average density over the three.
+    pub fn sample(&self, t_max: f64) -> (Option<f64>, Vec3) {
+        let ext = self.extinction();
+        let s = match (random() * 3.0) as usize {
+            0 => ext.x,
+            1 => ext.y,
+            _ => ext.z,
+        };
+        let t = if s > 0.0 {
+            -(1.0 - random()).ln() / s
+        } else {
+            f64::INFINITY
+        };
+        let avg = |v: Vec3| (v.x + v.y + v.z) / 3.0;
+        if t < t_max {
+            let tr = self.transmittance(t);
+            let pdf = avg(ext.mult(&tr));
+            (Some(t), self.scatter.mult(&tr) * (1.0 / pdf))
+        } else {
+            let tr = self.transmittance(t_max);
+            let pdf = avg(tr);
+            if pdf > 0.0 {
+                (None, tr * (1.0 / pdf))
+            } else {
+                (None, Vec3::zero())
+            }
+        }
+    }
+
+    // scene file fields
+    pub fn export(&self) -> String {
+        format!(
+            "absorb={} scatter={} g={}",
+            vec_str(self.absorb),
+            vec_str(self.scatter),
+            self.g
+        )
+    }
+}
+
+// Scattering inside a medium, as a material so that the lights can be
+// sampled there the same way as at surfaces. The normals mean nothing to it.
+pub struct Phase {
+    pub g: f64,
+}
+
+impl Phase {
+    // Henyey-Greenstein, for a path arriving along d and leaving along wi
+    fn value(&self, d: Vec3, wi: Vec3) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * d.dot(&wi);
+        (1.0 - g * g) / (4.0 * PI * denom * denom.max(0.0).sqrt())
+    }
+}
+
+impl Material for Phase {
+    fn sample(&self, d: Vec3, _n: Vec3, _nl: Vec3) -> Option<Sample> {
+        let g = self.g;
+        let u = random();
+        let cos = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let (a, b) = basis(d);
+        let (s, c) = (2.0 * PI * random()).sin_cos();
+        let wi = (a * (c * sin) + b * (s * sin) + d * cos).norm();
+        Some(Sample {
+            d: wi,
+            weight: Vec3::new(1.0, 1.0, 1.0),
+            pdf: Some(self.value(d, wi)),
+        })
+    }
+
+    fn eval(&self, d: Vec3, wi: Vec3, _nl: Vec3) -> Vec3 {
+        let p = self.value(d, wi);
+        Vec3::new(p, p, p)
+    }
+
+    fn pdf(&self, d: Vec3, wi: Vec3, _nl: Vec3) -> f64 {
+        self.value(d, wi)
+    }
+
+    fn emitted(&self) -> Vec3 {
+        Vec3::zero()
+    }
+
+    // never part of a scene
+    fn export(&self) -> String {
+        String::new()
+    }
+}
+
+// A shape that is only the edge of a medium: paths and shadow rays go
+// straight through it.
+pub struct Volume {
+    pub medium: Medium,
+}
+
+impl Volume {
+    pub fn new(medium: Medium) -> Volume {
+        Volume { medium }
+    }
+}
+
+impl Material for Volume {
+    fn sample(&self, d: Vec3, _n: Vec3, _nl: Vec3) -> Option<Sample> {
+        Some(Sample {
+            d,
+            weight: Vec3::new(1.0, 1.0, 1.0),
+            pdf: None,
+        })
+    }
+
+    fn emitted(&self) -> Vec3 {
+        Vec3::zero()
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        Some(&self.medium)
+    }
+
+    fn clear(&self) -> bool {
+        true
+    }
+
+    fn export(&self) -> String {
+        format!("refl=volume {}", self.medium.export())
+    }
+}
+
+// Another material, glass say, with a scattering medium inside: its own
+// absorption becomes the medium's.
+pub struct Filled {
+    pub mat: Box<dyn Material>,
+    pub medium: Medium,
+}
+
+impl Filled {
+    pub fn new(mat: Box<dyn Material>, scatter: Vec3, g: f64) -> Filled {
+        let medium = Medium::new(mat.absorption(), scatter, g);
+        Filled { mat, medium }
+    }
+}
+
+impl Material for Filled {
+    fn sample(&self, d: Vec3, n: Vec3, nl: Vec3) -> Option<Sample> {
+        self.mat.sample(d, n, nl)
+    }
+
+    fn eval(&self, d: Vec3, wi: Vec3, nl: Vec3) -> Vec3 {
+        self.mat.eval(d, wi, nl)
+    }
+
+    fn pdf(&self, d: Vec3, wi: Vec3, nl: Vec3) -> f64 {
+        self.mat.pdf(d, wi, nl)
+    }
+
+    fn emitted(&self) -> Vec3 {
+        self.mat.emitted()
+    }
+
+    fn tint(&self, uv: (f64, f64)) -> Vec3 {
+        self.mat.tint(uv)
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        Some(&self.medium)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "{} scatter={} g={}",
+            self.mat.export(),
+            vec_str(self.medium.scatter),
+            self.medium.g
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Glass;
+    use crate::seed_random;
+
+    fn close(a: Vec3, b: Vec3, tol: f64) -> bool {
+        (a.x - b.x).abs() <= tol && (a.y - b.y).abs() <= tol && (a.z - b.z).abs() <= tol
+    }
+
+    fn fog() -> Medium {
+        Medium::new(Vec3::new(0.01, 0.02, 0.0), Vec3::new(0.04, 0.1, 0.2), 0.3)
+    }
+
+    #[test]
+    fn transmittance() {
+        let m = fog();
+        let t = m.transmittance(5.0);
+        let want = Vec3::new((-0.25f64).exp(), (-0.6f64).exp(), (-1.0f64).exp());
+        assert!(close(t, want, 1e-12));
+        assert!(close(m.transmittance(0.0), Vec3::new(1.0, 1.0, 1.0), 0.0));
+    }
+
+    // Weighted, the paths that get through carry the transmittance and the
+    // ones that scatter the share of the light scattered on the way.
+    #[test]
+    fn free_flight() {
+        seed_random(21);
+        let m = fog();
+        let t_max = 8.0;
+        let n = 200000;
+        let (mut through, mut scattered) = (Vec3::zero(), Vec3::zero());
+        for _ in 0..n {
+            match m.sample(t_max) {
+                (Some(t), w) => {
+                    assert!((0.0..t_max).contains(&t));
+                    scattered = scattered + w * (1.0 / n as f64);
+                }
+                (None, w) => through = through + w * (1.0 / n as f64),
+            }
+        }
+        let tr = m.transmittance(t_max);
+        assert!(
+            close(through, tr, 0.01),
+            "{:?}",
+            (through.x, through.y, through.z)
+        );
+        let ext = m.extinction();
+        let want = |s: f64, e: f64, tr: f64| s / e * (1.0 - tr);
+        let want = Vec3::new(
+            want(m.scatter.x, ext.x, tr.x),
+            want(m.scatter.y, ext.y, tr.y),
+            want(m.scatter.z, ext.z, tr.z),
+        );
+        assert!(
+            close(scattered, want, 0.01),
+            "{:?}",
+            (scattered.x, scattered.y, scattered.z)
+        );
+    }
+
+    #[test]
+    fn empty() {
+        seed_random(22);
+        let m = Medium::new(Vec3::zero(), Vec3::zero(), 0.0);
+        for _ in 0..100 {
+            let (t, w) = m.sample(1e6);
+            assert!(t.is_none() && close(w, Vec3::new(1.0, 1.0, 1.0), 0.0));
+        }
+    }
+
+    // Henyey-Greenstein samples have the density the phase function gives,
+    // which integrates to one and has mean cosine g.
+    #[test]
+    fn phase() {
+        seed_random(23);
+        let d = Vec3::new(0.0, 0.6, 0.8);
+        let n = 200000;
+        for g in [0.0, 0.5, -0.7, 0.9] {
+            let p = Phase { g };
+            let mut cos = 0.0;
+            for _ in 0..n {
+                let s = p.sample(d, d, d).unwrap();
+                let want = p.pdf(d, s.d, d);
+                assert!((s.pdf.unwrap() - want).abs() < 1e-9 * want);
+                assert!(close(s.weight, p.eval(d, s.d, d) * (1.0 / want), 1e-9));
+                cos += s.d.dot(&d) / n as f64;
+            }
+            assert!((cos - g).abs() < 0.01, "{} {}", g, cos);
+            let total: f64 = (0..n)
+                .map(|_| {
+                    let z = 1.0 - 2.0 * random();
+                    let r = (1.0 - z * z).sqrt();
+                    let (s, c) = (2.0 * PI * random()).sin_cos();
+                    p.value(d, Vec3::new(r * c, r * s, z)) * 4.0 * PI
+                })
+                .sum::<f64>()
+                / n as f64;
+            assert!((total - 1.0).abs() < 0.03, "{} {}", g, total);
+        }
+    }
+
+    #[test]
+    fn materials() {
+        let v = Volume::new(fog());
+        assert!(v.clear() && v.medium().is_some());
+        let d = Vec3::new(0.0, 0.0, 1.0);
+        assert!(close(v.sample(d, d, d).unwrap().d, d, 0.0));
+        // filled glass absorbs through its medium
+        let a = Vec3::new(0.1, 0.2, 0.3);
+        let glass = Glass::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0), 1.5, a);
+        let f = Filled::new(Box::new(glass), Vec3::new(0.5, 0.5, 0.5), 0.2);
+        let m = f.medium().unwrap();
+        assert!(close(m.absorb, a, 0.0) && m.g == 0.2);
+    }
+}
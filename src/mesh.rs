@@ -0,0 +1,479 @@
+// Triangles and triangle meshes loaded from Wavefront OBJ files.
+
+use crate::bvh::Aabb;
+use crate::scenefile::{resolve, vec_str};
+use crate::shape::{Hit, Shape};
+use crate::transform::Transform;
+use crate::{random, Ray, Vec3, EPS};
+use std::fs;
+use std::path::Path;
+
+pub struct Triangle {
+    pub p: [Vec3; 3],
+    // per-vertex normals for smooth shading
+    pub n: Option<[Vec3; 3]>,
+    pub uv: Option<[(f64, f64); 3]>,
+    pub mat: usize,
+}
+
+impl Triangle {
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, mat: usize) -> Triangle {
+        Triangle {
+            p: [a, b, c],
+            n: None,
+            uv: None,
+            mat,
+        }
+    }
+}
+
+impl Shape for Triangle {
+    // Moller-Trumbore
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        let [a, b, c] = self.p;
+        let e1 = b - a;
+        let e2 = c - a;
+        let pv = ray.d % e2;
+        let det = e1.dot(&pv);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv = 1.0 / det;
+        let tv = ray.o - a;
+        let u = tv.dot(&pv) * inv;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qv = tv % e1;
+        let v = ray.d.dot(&qv) * inv;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(&qv) * inv;
+        if t <= EPS || t >= t_max {
+            return None;
+        }
+        let w = 1.0 - u - v;
+        let n = match self.n {
+            Some([n0, n1, n2]) => (n0 * w + n1 * u + n2 * v).norm(),
+            None => (e1 % e2).norm(),
+        };
+        let uv = match self.uv {
+            Some([t0, t1, t2]) => (
+                t0.0 * w + t1.0 * u + t2.0 * v,
+                t0.1 * w + t1.1 * u + t2.1 * v,
+            ),
+            None => (u, v),
+        };
+        Some(Hit {
+            t,
+            p: ray.o + ray.d * t,
+            n,
+            uv,
+            mat: self.mat,
+        })
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(&self.p)
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "triangle v0={} v1={} v2={}",
+            vec_str(self.p[0]),
+            vec_str(self.p[1]),
+            vec_str(self.p[2])
+        )
+    }
+
+    fn area(&self) -> f64 {
+        let [a, b, c] = self.p;
+        let n = (b - a) % (c - a);
+        0.5 * n.dot(&n).sqrt()
+    }
+
+    // the geometric normal, even on smooth meshes
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        let [a, b, c] = self.p;
+        let s = random().sqrt();
+        let r = random();
+        let p = a * (1.0 - s) + b * (s * (1.0 - r)) + c * (s * r);
+        Some((p, ((b - a) % (c - a)).norm()))
+    }
+}
+
+pub struct Mesh {
+    // absolute path of the OBJ file, for export
+    pub file: String,
+    pub xf: Transform,
+    pub tris: Vec<Triangle>,
+    pub mat: usize,
+    // running total of the triangles' areas, to sample them by
+    areas: Vec<f64>,
+}
+
+impl Mesh {
+    // Loads file, relative to dir, and moves it into place with xf.
+    pub fn load(file: &str, dir: &Path, xf: Transform, mat: usize) -> Result<Mesh, String> {
+        let path = resolve(file, dir)?;
+        let src = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        let tris = parse_obj(&src, &xf, mat)
+            .map_err(|(line, e)| format!("{}: line {}: {}", path, line, e))?;
+        if tris.is_empty() {
+            return Err(format!("{}: no faces", path));
+        }
+        let mut total = 0.0;
+        let areas = tris
+            .iter()
+            .map(|t| {
+                total += t.area();
+                total
+            })
+            .collect();
+        Ok(Mesh {
+            file: path,
+            xf,
+            tris,
+            mat,
+            areas,
+        })
+    }
+}
+
+// On its own a mesh tests every triangle; the scene BVH takes it apart with
+// parts() instead.
+impl Shape for Mesh {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        let mut hit: Option<Hit> = None;
+        for tri in &self.tris {
+            let t_max = hit.as_ref().map_or(t_max, |h| h.t);
+            if let Some(h) = tri.intersect(ray, t_max) {
+                hit = Some(h);
+            }
+        }
+        hit
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.tris
+            .iter()
+            .fold(Aabb::empty(), |b, t| b.union(&t.bounds()))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "mesh file={} scale={} rotate={} translate={}",
+            self.file,
+            vec_str(self.xf.scale),
+            vec_str(self.xf.rotate),
+            vec_str(self.xf.translate)
+        )
+    }
+
+    fn parts(&self) -> usize {
+        self.tris.len()
+    }
+
+    fn part_bounds(&self, i: usize) -> Aabb {
+        self.tris[i].bounds()
+    }
+
+    fn intersect_part(&self, i: usize, ray: &Ray, t_max: f64) -> Option<Hit> {
+        self.tris[i].intersect(ray, t_max)
+    }
+
+    fn area(&self) -> f64 {
+        *self.areas.last().unwrap()
+    }
+
+    // a triangle picked by area, then a point on it
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        let x = random() * self.area();
+        let i = self.areas.partition_point(|&a| a <= x);
+        self.tris[i.min(self.tris.len() - 1)].sample()
+    }
+}
+
+// Reads v, vt, vn and f records; everything else (groups, materials,
+// smoothing) is ignored. Polygons are split into fans.
+fn parse_obj(src: &str, xf: &Transform, mat: usize) -> Result<Vec<Triangle>, (usize, String)> {
+    let mut p: Vec<Vec3> = Vec::new();
+    let mut n: Vec<Vec3> = Vec::new();
+    let mut uv: Vec<(f64, f64)> = Vec::new();
+    let mut tris = Vec::new();
+    for (i, raw) in src.lines().enumerate() {
+        let err = |e: String| (i + 1, e);
+        let mut words = raw.split('#').next().unwrap().split_whitespace();
+        match words.next() {
+            Some("v") => p.push(xf.point(obj_vec(words).map_err(err)?)),
+            Some("vn") => n.push(xf.normal(obj_vec(words).map_err(err)?)),
+            Some("vt") => {
+                let t = obj_floats(words).map_err(err)?;
+                if t.is_empty() {
+                    return Err(err("vt: expected at least 1 number".to_string()));
+                }
+                uv.push((t[0], t.get(1).copied().unwrap_or(0.0)));
+            }
+            Some("f") => {
+                let mut face = Vec::new();
+                for w in words {
+                    let mut idx = w.split('/');
+                    let vi = obj_index(idx.next(), p.len()).map_err(err)?;
+                    let ti = obj_index(idx.next(), uv.len()).map_err(err)?;
+                    let ni = obj_index(idx.next(), n.len()).map_err(err)?;
+                    match vi {
+                        Some(vi) => face.push((vi, ti, ni)),
+                        None => return Err(err(format!("f: missing vertex in '{}'", w))),
+                    }
+                }
+                if face.len() < 3 {
+                    return Err(err("f: expected at least 3 vertices".to_string()));
+                }
+                for k in 1..face.len() - 1 {
+                    let f = [face[0], face[k], face[k + 1]];
+                    let normals = f.iter().all(|v| v.2.is_some());
+                    let uvs = f.iter().all(|v| v.1.is_some());
+                    tris.push(Triangle {
+                        p: f.map(|v| p[v.0]),
+                        n: if normals {
+                            Some(f.map(|v| n[v.2.unwrap()]))
+                        } else {
+                            None
+                        },
+                        uv: if uvs {
+                            Some(f.map(|v| uv[v.1.unwrap()]))
+                        } else {
+                            None
+                        },
+                        mat,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(tris)
+}
+
+fn obj_floats<'a>(words: impl Iterator<Item = &'a str>) -> Result<Vec<f64>, String> {
+    words
+        .map(|w| match w.parse::<f64>() {
+            Ok(x) if x.is_finite() => Ok(x),
+            _ => Err(format!("invalid number '{}'", w)),
+        })
+        .collect()
+}
+
+fn obj_vec<'a>(words: impl Iterator<Item = &'a str>) -> Result<Vec3, String> {
+    let v = obj_floats(words)?;
+    if v.len() < 3 {
+        return Err(format!("expected 3 numbers, got {}", v.len()));
+    }
+    Ok(Vec3::new(v[0], v[1], v[2]))
+}
+
+// 1-based, or negative to count back from the last element read so far.
+fn obj_index(s: Option<&str>, len: usize) -> Result<Option<usize>, String> {
+    let s = match s {
+        Some(s) if !s.is_empty() => s,
+        _ => return Ok(None),
+    };
+    let i: i64 = s.parse().map_err(|_| format!("invalid index '{}'", s))?;
+    let k = if i > 0 { i - 1 } else { len as i64 + i };
+    if i == 0 || k < 0 || k >= len as i64 {
+        return Err(format!("index {} out of range", i));
+    }
+    Ok(Some(k as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed_random;
+
+    fn identity() -> Transform {
+        Transform::new(Vec3::new(1.0, 1.0, 1.0), Vec3::zero(), Vec3::zero())
+    }
+
+    fn obj(src: &str) -> Vec<Triangle> {
+        parse_obj(src, &identity(), 3).unwrap()
+    }
+
+    fn error(src: &str) -> (usize, String) {
+        match parse_obj(src, &identity(), 0) {
+            Ok(_) => panic!("'{}' parsed", src),
+            Err(e) => e,
+        }
+    }
+
+    // unit right triangle in the z = 0 plane, facing +z
+    fn triangle() -> Triangle {
+        Triangle::new(
+            Vec3::zero(),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1,
+        )
+    }
+
+    fn down(x: f64, y: f64) -> Ray {
+        Ray::new(Vec3::new(x, y, 5.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn intersect() {
+        let tri = triangle();
+        let h = tri.intersect(&down(0.25, 0.5), 1e20).unwrap();
+        assert!((h.t - 5.0).abs() < 1e-12);
+        assert_eq!((h.n.x, h.n.y, h.n.z), (0.0, 0.0, 1.0));
+        assert!((h.uv.0 - 0.25).abs() < 1e-12 && (h.uv.1 - 0.5).abs() < 1e-12);
+        assert_eq!(h.mat, 1);
+        // from behind too, with the same normal
+        let r = Ray::new(Vec3::new(0.2, 0.2, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!((tri.intersect(&r, 1e20).unwrap().n.z - 1.0).abs() < 1e-12);
+        // outside, too far, or along the plane
+        assert!(tri.intersect(&down(0.6, 0.6), 1e20).is_none());
+        assert!(tri.intersect(&down(-0.1, 0.5), 1e20).is_none());
+        assert!(tri.intersect(&down(0.2, 0.2), 4.0).is_none());
+        let r = Ray::new(Vec3::new(-1.0, 0.2, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(tri.intersect(&r, 1e20).is_none());
+    }
+
+    #[test]
+    fn area_and_sample() {
+        seed_random(2);
+        let tri = Triangle::new(
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(3.0, 1.0, 1.0),
+            Vec3::new(1.0, 1.0, 5.0),
+            0,
+        );
+        assert!((tri.area() - 4.0).abs() < 1e-12);
+        let mut mean = Vec3::zero();
+        let n = 20000;
+        for _ in 0..n {
+            let (p, nl) = tri.sample().unwrap();
+            assert!((p.y - 1.0).abs() < 1e-12 && (nl.y.abs() - 1.0).abs() < 1e-12);
+            let (u, v) = ((p.x - 1.0) / 2.0, (p.z - 1.0) / 4.0);
+            assert!(u >= 0.0 && v >= 0.0 && u + v <= 1.0 + 1e-12);
+            mean = mean + p * (1.0 / n as f64);
+        }
+        // uniform samples average out at the centroid
+        assert!((mean.x - 5.0 / 3.0).abs() < 0.02 && (mean.z - 7.0 / 3.0).abs() < 0.04);
+    }
+
+    #[test]
+    fn faces() {
+        let src = "\
+# a unit square
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 2
+g square
+f 1/1/1 2/2/1 3/3/1 4/4/1
+f -4 -3 -2
+";
+        let tris = obj(src);
+        assert_eq!(tris.len(), 3);
+        // the quad is split into a fan from its first vertex
+        assert_eq!(tris[0].p[2].y, 1.0);
+        assert_eq!((tris[1].p[1].x, tris[1].p[2].x), (1.0, 0.0));
+        assert_eq!(tris[1].uv.unwrap()[1], (1.0, 1.0));
+        assert_eq!(tris[0].n.unwrap()[0].z, 1.0);
+        assert_eq!(tris[0].mat, 3);
+        // negative indices count back from the last vertex
+        assert_eq!(tris[2].p.map(|p| p.x), [0.0, 1.0, 1.0]);
+        assert!(tris[2].n.is_none() && tris[2].uv.is_none());
+    }
+
+    #[test]
+    fn moved_into_place() {
+        let xf = Transform::new(
+            Vec3::new(2.0, 2.0, 2.0),
+            Vec3::new(0.0, 0.0, 90.0),
+            Vec3::new(10.0, 0.0, 0.0),
+        );
+        let tris = parse_obj(
+            "v 1 0 0\nv 0 1 0\nv 0 0 1\nvn 1 0 0\nf 1//1 2//1 3//1\n",
+            &xf,
+            0,
+        )
+        .unwrap();
+        let p = tris[0].p[0];
+        assert!((p.x - 10.0).abs() < 1e-12 && (p.y - 2.0).abs() < 1e-12);
+        let n = tris[0].n.unwrap()[0];
+        assert!(n.x.abs() < 1e-12 && (n.y - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("v 1 2\n"),
+            (1, "expected 3 numbers, got 2".to_string())
+        );
+        assert_eq!(
+            error("v 0 0 0\nv 1 x 0\n"),
+            (2, "invalid number 'x'".to_string())
+        );
+        assert_eq!(
+            error("v inf 0 0\n"),
+            (1, "invalid number 'inf'".to_string())
+        );
+        assert_eq!(
+            error("vn 0 NaN 1\n"),
+            (1, "invalid number 'NaN'".to_string())
+        );
+        assert_eq!(
+            error("vt 0 -inf\n"),
+            (1, "invalid number '-inf'".to_string())
+        );
+        assert_eq!(
+            error("vt\n"),
+            (1, "vt: expected at least 1 number".to_string())
+        );
+        let three = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+        assert_eq!(
+            error(&format!("{}f 1 2\n", three)),
+            (4, "f: expected at least 3 vertices".to_string())
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2 4\n", three)),
+            (4, "index 4 out of range".to_string())
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2 -4\n", three)),
+            (4, "index -4 out of range".to_string())
+        );
+        assert_eq!(
+            error(&format!("{}f 0 1 2\n", three)),
+            (4, "index 0 out of range".to_string())
+        );
+        assert_eq!(
+            error(&format!("{}f 1/2 2 3\n", three)),
+            (4, "index 2 out of range".to_string())
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2 a\n", three)),
+            (4, "invalid index 'a'".to_string())
+        );
+        assert_eq!(
+            error(&format!("{}vt 0 0\nf 1 /1 3\n", three)),
+            (5, "f: missing vertex in '/1'".to_string())
+        );
+    }
+}
@@ -0,0 +1,448 @@
+// GGX microfacet reflection and refraction for rough metals and frosted
+// glass, sampling the normals visible from the incoming direction (Heitz,
+// "Sampling the GGX Distribution of Visible Normals", 2018).
+
+use crate::material::{fields, Material, Sample};
+use crate::scenefile::vec_str;
+use crate::shape::basis;
+use crate::{random, Vec3};
+use std::f64::consts::PI;
+
+// Complex indices of refraction (n, k) of some metals at red, green and
+// blue wavelengths.
+#[derive(Clone, Copy)]
+pub enum Metal {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl Metal {
+    pub fn name(self) -> &'static str {
+        match self {
+            Metal::Gold => "gold",
+            Metal::Copper => "copper",
+            Metal::Aluminium => "aluminium",
+            Metal::Silver => "silver",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Metal> {
+        match s {
+            "gold" => Some(Metal::Gold),
+            "copper" => Some(Metal::Copper),
+            "aluminium" => Some(Metal::Aluminium),
+            "silver" => Some(Metal::Silver),
+            _ => None,
+        }
+    }
+
+    #[rustfmt::skip]
+    fn ior(self) -> (Vec3, Vec3) {
+        match self {
+            Metal::Gold =>      (Vec3::new(0.143, 0.374, 1.442), Vec3::new(3.983, 2.385, 1.603)),
+            Metal::Copper =>    (Vec3::new(0.200, 0.924, 1.102), Vec3::new(3.912, 2.452, 2.142)),
+            Metal::Aluminium => (Vec3::new(1.657, 0.880, 0.521), Vec3::new(9.224, 6.270, 4.837)),
+            Metal::Silver =>    (Vec3::new(0.155, 0.117, 0.138), Vec3::new(4.828, 3.122, 2.147)),
+        }
+    }
+
+    // Fresnel reflectance
+    fn fresnel(self, cos: f64) -> Vec3 {
+        let (eta, k) = self.ior();
+        Vec3::new(
+            conductor(cos, eta.x, k.x),
+            conductor(cos, eta.y, k.y),
+            conductor(cos, eta.z, k.z),
+        )
+    }
+}
+
+// Sampling falls apart for smaller alphas, which look polished anyway.
+const MIN_ALPHA: f64 = 1e-4;
+
+// Directions are taken into a frame with the facing normal along z.
+struct Frame {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    fn new(w: Vec3) -> Frame {
+        let (u, v) = basis(w);
+        Frame { u, v, w }
+    }
+
+    fn local(&self, d: Vec3) -> Vec3 {
+        Vec3::new(d.dot(&self.u), d.dot(&self.v), d.dot(&self.w))
+    }
+
+    fn world(&self, d: Vec3) -> Vec3 {
+        self.u * d.x + self.v * d.y + self.w * d.z
+    }
+}
+
+fn alpha(rough: f64) -> f64 {
+    (rough * rough).max(MIN_ALPHA)
+}
+
+// microfacet normal, with density proportional to how much of it wo sees
+fn sample_vndf(wo: Vec3, a: f64) -> Vec3 {
+    let vh = Vec3::new(a * wo.x, a * wo.y, wo.z).norm();
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len2 > 0.0 {
+        Vec3::new(-vh.y, vh.x, 0.0) * (1.0 / len2.sqrt())
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = vh % t1;
+    let r = random().sqrt();
+    let (s, c) = (2.0 * PI * random()).sin_cos();
+    let p1 = r * c;
+    let sv = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - sv) * (1.0 - p1 * p1).max(0.0).sqrt() + sv * r * s;
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    Vec3::new(a * nh.x, a * nh.y, nh.z.max(0.0)).norm()
+}
+
+// GGX distribution of microfacet normals
+fn ggx(m: Vec3, a: f64) -> f64 {
+    let a2 = a * a;
+    let t = (m.x * m.x + m.y * m.y) / a2 + m.z * m.z;
+    1.0 / (PI * a2 * t * t)
+}
+
+// Smith's lambda, for either side of the surface
+fn lambda(w: Vec3, a: f64) -> f64 {
+    let tan2 = (w.x * w.x + w.y * w.y) / (w.z * w.z);
+    ((1.0 + a * a * tan2).sqrt() - 1.0) * 0.5
+}
+
+// What is left of the BSDF over the density of sample_vndf(): the
+// height-correlated shadowing-masking term over the masking of wo.
+fn weight(wo: Vec3, wi: Vec3, a: f64) -> f64 {
+    let lo = lambda(wo, a);
+    (1.0 + lo) / (1.0 + lo + lambda(wi, a))
+}
+
+fn reflect(wo: Vec3, m: Vec3) -> Vec3 {
+    m * (2.0 * wo.dot(&m)) - wo
+}
+
+// Fresnel reflectance of a conductor with complex index eta + ik.
+fn conductor(cos: f64, eta: f64, k: f64) -> f64 {
+    let c2 = cos * cos;
+    let s2 = 1.0 - c2;
+    let t0 = eta * eta - k * k - s2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let t1 = a2b2 + c2;
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = c2 * a2b2 + s2 * s2;
+    let t4 = t2 * s2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+// GGX reflection off a metal: tinted by c, with the Fresnel term of a named
+// metal or none at all.
+pub struct RoughMetal {
+    pub e: Vec3,
+    pub c: Vec3,
+    pub rough: f64,
+    pub metal: Option<Metal>,
+}
+
+impl RoughMetal {
+    pub fn new(e: Vec3, c: Vec3, rough: f64, metal: Option<Metal>) -> RoughMetal {
+        RoughMetal { e, c, rough, metal }
+    }
+
+    fn fresnel(&self, cos: f64) -> Vec3 {
+        match self.metal {
+            Some(metal) => self.c.mult(&metal.fresnel(cos)),
+            None => self.c,
+        }
+    }
+}
+
+impl Material for RoughMetal {
+    fn sample(&self, d: Vec3, _n: Vec3, nl: Vec3) -> Option<Sample> {
+        let frame = Frame::new(nl);
+        let a = alpha(self.rough);
+        let wo = frame.local(d * -1.0);
+        let m = sample_vndf(wo, a);
+        let wi = reflect(wo, m);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(Sample {
+            d: frame.world(wi),
+            weight: self.fresnel(wo.dot(&m)) * weight(wo, wi, a),
+            pdf: Some(ggx(m, a) / (4.0 * (1.0 + lambda(wo, a)) * wo.z)),
+        })
+    }
+
+    fn eval(&self, d: Vec3, wi: Vec3, nl: Vec3) -> Vec3 {
+        let frame = Frame::new(nl);
+        let a = alpha(self.rough);
+        let (wo, wi) = (frame.local(d * -1.0), frame.local(wi));
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return Vec3::zero();
+        }
+        let m = (wo + wi).norm();
+        let g = 1.0 / (1.0 + lambda(wo, a) + lambda(wi, a));
+        self.fresnel(wo.dot(&m)) * (ggx(m, a) * g / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, d: Vec3, wi: Vec3, nl: Vec3) -> f64 {
+        let frame = Frame::new(nl);
+        let a = alpha(self.rough);
+        let (wo, wi) = (frame.local(d * -1.0), frame.local(wi));
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return 0.0;
+        }
+        let m = (wo + wi).norm();
+        ggx(m, a) / (4.0 * (1.0 + lambda(wo, a)) * wo.z)
+    }
+
+    fn emitted(&self) -> Vec3 {
+        self.e
+    }
+
+    fn export(&self) -> String {
+        let mut s = format!("{} rough={}", fields(self.e, self.c, "metal"), self.rough);
+        if let Some(metal) = self.metal {
+            s += &format!(" metal={}", metal.name());
+        }
+        s
+    }
+}
+
+// Rough dielectric of index ior in air. Reflection or refraction at the
+// sampled microfacet is picked by Schlick's Fresnel term, as Glass does, so
+// it is never weighted against light sampling.
+pub struct Frosted {
+    pub e: Vec3,
+    pub c: Vec3,
+    pub rough: f64,
+    pub ior: f64,
+    pub absorb: Vec3,
+}
+
+impl Frosted {
+    pub fn new(e: Vec3, c: Vec3, rough: f64, ior: f64, absorb: Vec3) -> Frosted {
+        Frosted {
+            e,
+            c,
+            rough,
+            ior,
+            absorb,
+        }
+    }
+}
+
+impl Material for Frosted {
+    fn sample(&self, d: Vec3, n: Vec3, nl: Vec3) -> Option<Sample> {
+        let frame = Frame::new(nl);
+        let a = alpha(self.rough);
+        let wo = frame.local(d * -1.0);
+        let m = sample_vndf(wo, a);
+        let (nc, nt) = (1.0, self.ior);
+        let into = n.dot(&nl) > 0.0;
+        let nnt = if into { nc / nt } else { nt / nc };
+        let cos_i = wo.dot(&m);
+        let cos2t = 1.0 - nnt * nnt * (1.0 - cos_i * cos_i);
+        let re = if cos2t < 0.0 {
+            1.0
+        } else {
+            let r0 = (nt - nc) * (nt - nc) / ((nt + nc) * (nt + nc));
+            let c = 1.0 - if into { cos_i } else { cos2t.sqrt() };
+            r0 + (1.0 - r0) * c * c * c * c * c
+        };
+        let wi = if random() < re {
+            let wi = reflect(wo, m);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = m * (nnt * cos_i - cos2t.sqrt()) - wo * nnt;
+            if wi.z >= 0.0 {
+                return None;
+            }
+            wi
+        };
+        Some(Sample {
+            d: frame.world(wi),
+            weight: self.c * weight(wo, wi, a),
+            pdf: None,
+        })
+    }
+
+    fn emitted(&self) -> Vec3 {
+        self.e
+    }
+
+    fn absorption(&self) -> Vec3 {
+        self.absorb
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "{} rough={} ior={} absorb={}",
+            fields(self.e, self.c, "frosted"),
+            self.rough,
+            self.ior,
+            vec_str(self.absorb)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed_random;
+
+    fn up() -> Vec3 {
+        Vec3::new(0.0, 0.0, 1.0)
+    }
+
+    // arriving at angle theta (degrees) from the normal
+    fn arriving(theta: f64) -> Vec3 {
+        let (s, c) = theta.to_radians().sin_cos();
+        Vec3::new(s, 0.0, -c)
+    }
+
+    fn uniform_hemisphere() -> Vec3 {
+        let z = random();
+        let r = (1.0 - z * z).sqrt();
+        let (s, c) = (2.0 * PI * random()).sin_cos();
+        Vec3::new(r * c, r * s, z)
+    }
+
+    // sample() gives the density pdf() has for its direction and a weight
+    // of eval() over it
+    #[test]
+    fn sample_matches_pdf() {
+        seed_random(12);
+        for rough in [0.05, 0.3, 0.8] {
+            let m = RoughMetal::new(
+                Vec3::zero(),
+                Vec3::new(0.9, 0.8, 0.7),
+                rough,
+                Some(Metal::Gold),
+            );
+            for theta in [0.0, 30.0, 75.0] {
+                let d = arriving(theta);
+                for _ in 0..500 {
+                    let s = match m.sample(d, up(), up()) {
+                        Some(s) => s,
+                        None => continue,
+                    };
+                    let pdf = m.pdf(d, s.d, up());
+                    let want = s.pdf.unwrap();
+                    assert!((pdf - want).abs() < 1e-9 * want, "{} {}", pdf, want);
+                    let f = m.eval(d, s.d, up()) * (1.0 / pdf);
+                    let e = f - s.weight;
+                    assert!(e.dot(&e).sqrt() < 1e-9, "{} {}", f.x, s.weight.x);
+                }
+            }
+        }
+    }
+
+    // The density integrates to the share of samples that come out above
+    // the surface.
+    #[test]
+    fn pdf_integrates() {
+        seed_random(13);
+        let n = 200000;
+        for rough in [0.4, 0.8] {
+            let m = RoughMetal::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0), rough, None);
+            for theta in [0.0, 60.0] {
+                let d = arriving(theta);
+                let total: f64 = (0..n)
+                    .map(|_| m.pdf(d, uniform_hemisphere(), up()) * 2.0 * PI)
+                    .sum::<f64>()
+                    / n as f64;
+                let above = (0..n).filter(|_| m.sample(d, up(), up()).is_some()).count();
+                let above = above as f64 / n as f64;
+                assert!((total - above).abs() < 0.02, "{} {}", total, above);
+            }
+        }
+        // nothing from below or to below
+        let m = RoughMetal::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0), 0.5, None);
+        assert_eq!(m.pdf(arriving(20.0), Vec3::new(0.0, 0.6, -0.8), up()), 0.0);
+        assert_eq!(m.pdf(Vec3::new(0.0, 0.6, 0.8), up(), up()), 0.0);
+    }
+
+    // Without Fresnel or tint a smooth enough surface loses next to nothing.
+    #[test]
+    fn white_furnace() {
+        seed_random(14);
+        let m = RoughMetal::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0), 0.2, None);
+        let n = 20000;
+        let total: f64 = (0..n)
+            .filter_map(|_| m.sample(arriving(20.0), up(), up()))
+            .map(|s| s.weight.x)
+            .sum();
+        let albedo = total / n as f64;
+        assert!(albedo <= 1.0 && albedo > 0.97, "{}", albedo);
+    }
+
+    #[test]
+    fn conductors() {
+        for metal in [Metal::Gold, Metal::Copper, Metal::Aluminium, Metal::Silver] {
+            assert!(Metal::from_name(metal.name()).is_some_and(|m| m.name() == metal.name()));
+            let (eta, k) = metal.ior();
+            let f = metal.fresnel(1.0);
+            let normal =
+                |n: f64, k: f64| ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+            assert!((f.x - normal(eta.x, k.x)).abs() < 1e-9);
+            assert!((f.z - normal(eta.z, k.z)).abs() < 1e-9);
+            // all of it at grazing angles
+            assert!((metal.fresnel(1e-9).y - 1.0).abs() < 1e-6);
+        }
+        assert!(Metal::from_name("lead").is_none());
+    }
+
+    // At the smallest roughness frosted glass acts like clear glass: at 30
+    // degrees it reflects about (n-1)^2/(n+1)^2 and refracts the rest.
+    #[test]
+    fn frosted_smooth() {
+        seed_random(15);
+        let g = Frosted::new(
+            Vec3::zero(),
+            Vec3::new(1.0, 1.0, 1.0),
+            0.0,
+            1.5,
+            Vec3::zero(),
+        );
+        let n = 20000;
+        let d = arriving(30.0);
+        let mut reflected = 0;
+        for _ in 0..n {
+            let s = g.sample(d, up(), up()).unwrap();
+            assert!(s.pdf.is_none());
+            if s.d.z > 0.0 {
+                reflected += 1;
+                assert!((s.d.x - 0.5).abs() < 0.02);
+            } else {
+                assert!((s.d.x - 0.5 / 1.5).abs() < 0.02, "{}", s.d.x);
+            }
+        }
+        let re = reflected as f64 / n as f64;
+        assert!((re - 0.04).abs() < 0.01, "{}", re);
+        // past the critical angle from inside it all reflects
+        let d = Vec3::new(0.8, 0.0, 0.6);
+        let down = up() * -1.0;
+        for _ in 0..100 {
+            if let Some(s) = g.sample(d, up(), down) {
+                assert!(s.d.z < 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,1034 @@
+// More analytic primitives: disks, capped cylinders and cones, boxes and tori.
+// Each works in a frame of its own with w along its axis.
+
+use crate::bvh::Aabb;
+use crate::scenefile::vec_str;
+use crate::shape::{basis, Hit, Shape, Span};
+use crate::{random, Ray, Vec3, EPS};
+use std::f64::consts::PI;
+
+struct Frame {
+    p: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    fn new(p: Vec3, w: Vec3) -> Frame {
+        let w = w.norm();
+        let (u, v) = basis(w);
+        Frame { p, u, v, w }
+    }
+
+    // the ray in frame coordinates; distances along it are unchanged
+    fn local(&self, ray: &Ray) -> (Vec3, Vec3) {
+        let o = ray.o - self.p;
+        let d = ray.d;
+        (
+            Vec3::new(o.dot(&self.u), o.dot(&self.v), o.dot(&self.w)),
+            Vec3::new(d.dot(&self.u), d.dot(&self.v), d.dot(&self.w)),
+        )
+    }
+
+    fn vector(&self, v: Vec3) -> Vec3 {
+        self.u * v.x + self.v * v.y + self.w * v.z
+    }
+
+    fn point(&self, q: Vec3) -> Vec3 {
+        self.p + self.vector(q)
+    }
+}
+
+// Bounds of a circle of radius r about c in the plane normal to the unit
+// vector n.
+fn circle_bounds(c: Vec3, n: Vec3, r: f64) -> Aabb {
+    let e = |a: f64| r * (1.0 - a * a).max(0.0).sqrt();
+    let e = Vec3::new(e(n.x), e(n.y), e(n.z));
+    Aabb::from_points(&[c - e, c + e])
+}
+
+// Convex solids find the stretch of the line inside them in their frame;
+// normal and uv then follow from the local point.
+trait Convex {
+    fn frame(&self) -> &Frame;
+    fn mat(&self) -> usize;
+    fn interval(&self, o: Vec3, d: Vec3) -> Option<(f64, f64)>;
+    // outward normal and uv at a local point on the surface
+    fn surface(&self, q: Vec3) -> (Vec3, (f64, f64));
+
+    fn hit_at(&self, ray: &Ray, o: Vec3, d: Vec3, t: f64) -> Hit {
+        let (n, uv) = self.surface(o + d * t);
+        Hit {
+            t,
+            p: ray.o + ray.d * t,
+            n: self.frame().vector(n),
+            uv,
+            mat: self.mat(),
+        }
+    }
+
+    fn first_hit(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        let (o, d) = self.frame().local(ray);
+        let (t0, t1) = self.interval(o, d)?;
+        let t = if t0 > EPS { t0 } else { t1 };
+        if t <= EPS || t >= t_max {
+            return None;
+        }
+        Some(self.hit_at(ray, o, d, t))
+    }
+
+    fn span(&self, ray: &Ray) -> Vec<Span> {
+        let (o, d) = self.frame().local(ray);
+        match self.interval(o, d) {
+            Some((t0, t1)) => vec![Span {
+                enter: self.hit_at(ray, o, d, t0),
+                exit: self.hit_at(ray, o, d, t1),
+            }],
+            None => Vec::new(),
+        }
+    }
+}
+
+// Where o + t d lies between 0 and s along one axis, as (t0, t1).
+fn slab(o: f64, d: f64, s: f64) -> Option<(f64, f64)> {
+    if d.abs() < 1e-12 {
+        return if (0.0..=s).contains(&o) {
+            Some((-f64::INFINITY, f64::INFINITY))
+        } else {
+            None
+        };
+    }
+    let (a, b) = (-o / d, (s - o) / d);
+    Some((a.min(b), a.max(b)))
+}
+
+fn overlap(a: (f64, f64), b: (f64, f64)) -> Option<(f64, f64)> {
+    let (t0, t1) = (a.0.max(b.0), a.1.min(b.1));
+    if t0 <= t1 {
+        Some((t0, t1))
+    } else {
+        None
+    }
+}
+
+// Real roots of a t^2 + 2 b t + c.
+fn quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        return if b.abs() < 1e-12 {
+            Vec::new()
+        } else {
+            vec![-c / (2.0 * b)]
+        };
+    }
+    let disc = b * b - a * c;
+    if disc < 0.0 {
+        return Vec::new();
+    }
+    // avoids cancellation
+    let q = -(b + b.signum() * disc.sqrt());
+    if q == 0.0 {
+        return vec![0.0, 0.0];
+    }
+    vec![q / a, c / q]
+}
+
+fn angle(x: f64, y: f64) -> f64 {
+    0.5 + y.atan2(x) / (2.0 * PI)
+}
+
+// Flat disk about p facing n.
+pub struct Disk {
+    pub p: Vec3,
+    pub n: Vec3,
+    pub rad: f64,
+    pub mat: usize,
+    f: Frame,
+}
+
+impl Disk {
+    pub fn new(p: Vec3, n: Vec3, rad: f64, mat: usize) -> Disk {
+        let f = Frame::new(p, n);
+        Disk {
+            p,
+            n: f.w,
+            rad,
+            mat,
+            f,
+        }
+    }
+}
+
+impl Shape for Disk {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        let (o, d) = self.f.local(ray);
+        if d.z.abs() < 1e-12 {
+            return None;
+        }
+        let t = -o.z / d.z;
+        if t <= EPS || t >= t_max {
+            return None;
+        }
+        let q = o + d * t;
+        let r2 = q.x * q.x + q.y * q.y;
+        if r2 > self.rad * self.rad {
+            return None;
+        }
+        Some(Hit {
+            t,
+            p: ray.o + ray.d * t,
+            n: self.n,
+            uv: (angle(q.x, q.y), r2.sqrt() / self.rad),
+            mat: self.mat,
+        })
+    }
+
+    fn bounds(&self) -> Aabb {
+        circle_bounds(self.p, self.n, self.rad)
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "disk p={} n={} rad={}",
+            vec_str(self.p),
+            vec_str(self.n),
+            self.rad
+        )
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.rad * self.rad
+    }
+
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        let r = self.rad * random().sqrt();
+        let (s, c) = (2.0 * PI * random()).sin_cos();
+        Some((self.f.point(Vec3::new(r * c, r * s, 0.0)), self.n))
+    }
+}
+
+// Cylinder of radius rad from p0 to p1, closed at both ends.
+pub struct Cylinder {
+    pub p0: Vec3,
+    pub p1: Vec3,
+    pub rad: f64,
+    pub mat: usize,
+    f: Frame,
+    h: f64,
+}
+
+impl Cylinder {
+    pub fn new(p0: Vec3, p1: Vec3, rad: f64, mat: usize) -> Cylinder {
+        let a = p1 - p0;
+        Cylinder {
+            p0,
+            p1,
+            rad,
+            mat,
+            f: Frame::new(p0, a),
+            h: a.dot(&a).sqrt(),
+        }
+    }
+}
+
+impl Convex for Cylinder {
+    fn frame(&self) -> &Frame {
+        &self.f
+    }
+
+    fn mat(&self) -> usize {
+        self.mat
+    }
+
+    fn interval(&self, o: Vec3, d: Vec3) -> Option<(f64, f64)> {
+        let caps = slab(o.z, d.z, self.h)?;
+        let a = d.x * d.x + d.y * d.y;
+        let c = o.x * o.x + o.y * o.y - self.rad * self.rad;
+        let side = if a < 1e-12 {
+            if c > 0.0 {
+                return None;
+            }
+            (-f64::INFINITY, f64::INFINITY)
+        } else {
+            match quadratic(a, o.x * d.x + o.y * d.y, c)[..] {
+                [t0, t1] => (t0.min(t1), t0.max(t1)),
+                _ => return None,
+            }
+        };
+        overlap(caps, side)
+    }
+
+    fn surface(&self, q: Vec3) -> (Vec3, (f64, f64)) {
+        let r = (q.x * q.x + q.y * q.y).sqrt();
+        let u = angle(q.x, q.y);
+        let side = (r - self.rad).abs();
+        if q.z.abs() < side && q.z.abs() <= (q.z - self.h).abs() {
+            (Vec3::new(0.0, 0.0, -1.0), (u, r / self.rad))
+        } else if (q.z - self.h).abs() < side {
+            (Vec3::new(0.0, 0.0, 1.0), (u, r / self.rad))
+        } else {
+            (Vec3::new(q.x / r, q.y / r, 0.0), (u, q.z / self.h))
+        }
+    }
+}
+
+impl Shape for Cylinder {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        self.first_hit(ray, t_max)
+    }
+
+    fn bounds(&self) -> Aabb {
+        circle_bounds(self.p0, self.f.w, self.rad)
+            .union(&circle_bounds(self.p1, self.f.w, self.rad))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "cylinder p0={} p1={} rad={}",
+            vec_str(self.p0),
+            vec_str(self.p1),
+            self.rad
+        )
+    }
+
+    fn solid(&self) -> bool {
+        true
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        self.span(ray)
+    }
+
+    fn area(&self) -> f64 {
+        2.0 * PI * self.rad * (self.h + self.rad)
+    }
+
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        let (s, c) = (2.0 * PI * random()).sin_cos();
+        // side or one of the caps, by area
+        let k = random() * (self.h + self.rad);
+        let (q, n) = if k < self.h {
+            (
+                Vec3::new(self.rad * c, self.rad * s, random() * self.h),
+                Vec3::new(c, s, 0.0),
+            )
+        } else {
+            let r = self.rad * random().sqrt();
+            let top = random() < 0.5;
+            (
+                Vec3::new(r * c, r * s, if top { self.h } else { 0.0 }),
+                Vec3::new(0.0, 0.0, if top { 1.0 } else { -1.0 }),
+            )
+        };
+        Some((self.f.point(q), self.f.vector(n)))
+    }
+}
+
+// Cone with a base of radius rad about p0 and its tip at p1.
+pub struct Cone {
+    pub p0: Vec3,
+    pub p1: Vec3,
+    pub rad: f64,
+    pub mat: usize,
+    f: Frame,
+    h: f64,
+    // radius lost per unit of height
+    k: f64,
+}
+
+impl Cone {
+    pub fn new(p0: Vec3, p1: Vec3, rad: f64, mat: usize) -> Cone {
+        let a = p1 - p0;
+        let h = a.dot(&a).sqrt();
+        Cone {
+            p0,
+            p1,
+            rad,
+            mat,
+            f: Frame::new(p0, a),
+            h,
+            k: rad / h,
+        }
+    }
+
+    fn slant(&self) -> f64 {
+        (self.rad * self.rad + self.h * self.h).sqrt()
+    }
+}
+
+impl Convex for Cone {
+    fn frame(&self) -> &Frame {
+        &self.f
+    }
+
+    fn mat(&self) -> usize {
+        self.mat
+    }
+
+    // the crossings with the side between base and tip, and with the base
+    fn interval(&self, o: Vec3, d: Vec3) -> Option<(f64, f64)> {
+        let k2 = self.k * self.k;
+        let hz = self.h - o.z;
+        let mut ts: Vec<f64> = quadratic(
+            d.x * d.x + d.y * d.y - k2 * d.z * d.z,
+            o.x * d.x + o.y * d.y + k2 * hz * d.z,
+            o.x * o.x + o.y * o.y - k2 * hz * hz,
+        )
+        .into_iter()
+        .filter(|t| (0.0..=self.h).contains(&(o.z + d.z * t)))
+        .collect();
+        if d.z.abs() > 1e-12 {
+            let t = -o.z / d.z;
+            let q = o + d * t;
+            if q.x * q.x + q.y * q.y <= self.rad * self.rad {
+                ts.push(t);
+            }
+        }
+        let t0 = ts.iter().copied().fold(f64::INFINITY, f64::min);
+        let t1 = ts.iter().copied().fold(-f64::INFINITY, f64::max);
+        if ts.len() < 2 || t0 == t1 {
+            return None;
+        }
+        Some((t0, t1))
+    }
+
+    fn surface(&self, q: Vec3) -> (Vec3, (f64, f64)) {
+        let r = (q.x * q.x + q.y * q.y).sqrt();
+        let u = angle(q.x, q.y);
+        let side = (r - self.k * (self.h - q.z)).abs() * self.h / self.slant();
+        if q.z.abs() < side {
+            (Vec3::new(0.0, 0.0, -1.0), (u, r / self.rad))
+        } else if r == 0.0 {
+            (Vec3::new(0.0, 0.0, 1.0), (u, 1.0))
+        } else {
+            (
+                Vec3::new(q.x / r, q.y / r, self.k).norm(),
+                (u, q.z / self.h),
+            )
+        }
+    }
+}
+
+impl Shape for Cone {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        self.first_hit(ray, t_max)
+    }
+
+    fn bounds(&self) -> Aabb {
+        circle_bounds(self.p0, self.f.w, self.rad).grow(self.p1)
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "cone p0={} p1={} rad={}",
+            vec_str(self.p0),
+            vec_str(self.p1),
+            self.rad
+        )
+    }
+
+    fn solid(&self) -> bool {
+        true
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        self.span(ray)
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.rad * (self.rad + self.slant())
+    }
+
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        let (s, c) = (2.0 * PI * random()).sin_cos();
+        let (q, n) = if random() * (self.rad + self.slant()) < self.rad {
+            let r = self.rad * random().sqrt();
+            (Vec3::new(r * c, r * s, 0.0), Vec3::new(0.0, 0.0, -1.0))
+        } else {
+            // the side grows linearly away from the tip
+            let a = random().sqrt();
+            let r = self.rad * a;
+            (
+                Vec3::new(r * c, r * s, self.h * (1.0 - a)),
+                Vec3::new(c, s, self.k).norm(),
+            )
+        };
+        Some((self.f.point(q), self.f.vector(n)))
+    }
+}
+
+// Box with corner p and perpendicular edges u, v and w.
+pub struct Cuboid {
+    pub p: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub mat: usize,
+    f: Frame,
+    // edge lengths
+    s: [f64; 3],
+}
+
+impl Cuboid {
+    pub fn new(p: Vec3, u: Vec3, v: Vec3, w: Vec3, mat: usize) -> Cuboid {
+        let len = |e: Vec3| e.dot(&e).sqrt();
+        Cuboid {
+            p,
+            u,
+            v,
+            w,
+            mat,
+            f: Frame {
+                p,
+                u: u.norm(),
+                v: v.norm(),
+                w: w.norm(),
+            },
+            s: [len(u), len(v), len(w)],
+        }
+    }
+
+    pub fn aligned(min: Vec3, max: Vec3, mat: usize) -> Cuboid {
+        let d = max - min;
+        Cuboid::new(
+            min,
+            Vec3::new(d.x, 0.0, 0.0),
+            Vec3::new(0.0, d.y, 0.0),
+            Vec3::new(0.0, 0.0, d.z),
+            mat,
+        )
+    }
+}
+
+impl Convex for Cuboid {
+    fn frame(&self) -> &Frame {
+        &self.f
+    }
+
+    fn mat(&self) -> usize {
+        self.mat
+    }
+
+    fn interval(&self, o: Vec3, d: Vec3) -> Option<(f64, f64)> {
+        let x = slab(o.x, d.x, self.s[0])?;
+        let y = slab(o.y, d.y, self.s[1])?;
+        let z = slab(o.z, d.z, self.s[2])?;
+        overlap(overlap(x, y)?, z)
+    }
+
+    // the nearest face
+    fn surface(&self, q: Vec3) -> (Vec3, (f64, f64)) {
+        let [sx, sy, sz] = self.s;
+        let faces = [
+            (q.x, Vec3::new(-1.0, 0.0, 0.0), (q.y / sy, q.z / sz)),
+            (sx - q.x, Vec3::new(1.0, 0.0, 0.0), (q.y / sy, q.z / sz)),
+            (q.y, Vec3::new(0.0, -1.0, 0.0), (q.x / sx, q.z / sz)),
+            (sy - q.y, Vec3::new(0.0, 1.0, 0.0), (q.x / sx, q.z / sz)),
+            (q.z, Vec3::new(0.0, 0.0, -1.0), (q.x / sx, q.y / sy)),
+            (sz - q.z, Vec3::new(0.0, 0.0, 1.0), (q.x / sx, q.y / sy)),
+        ];
+        let f = faces
+            .iter()
+            .min_by(|a, b| a.0.abs().total_cmp(&b.0.abs()))
+            .unwrap();
+        (f.1, f.2)
+    }
+}
+
+impl Shape for Cuboid {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        self.first_hit(ray, t_max)
+    }
+
+    fn bounds(&self) -> Aabb {
+        let (p, u, v, w) = (self.p, self.u, self.v, self.w);
+        Aabb::from_points(&[
+            p,
+            p + u,
+            p + v,
+            p + w,
+            p + u + v,
+            p + u + w,
+            p + v + w,
+            p + u + v + w,
+        ])
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "box p={} u={} v={} w={}",
+            vec_str(self.p),
+            vec_str(self.u),
+            vec_str(self.v),
+            vec_str(self.w)
+        )
+    }
+
+    fn solid(&self) -> bool {
+        true
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        self.span(ray)
+    }
+
+    fn area(&self) -> f64 {
+        let [a, b, c] = self.s;
+        2.0 * (a * b + b * c + c * a)
+    }
+
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        let [a, b, c] = self.s;
+        // a pair of opposite faces by area, then one of the two
+        let k = random() * (a * b + b * c + c * a);
+        let (i, j, n) = if k < a * b {
+            (self.u, self.v, self.f.w)
+        } else if k < a * b + b * c {
+            (self.v, self.w, self.f.u)
+        } else {
+            (self.w, self.u, self.f.v)
+        };
+        let q = self.p + i * random() + j * random();
+        let far = n * (n.dot(&(self.u + self.v + self.w)));
+        Some(if random() < 0.5 {
+            (q, n * -1.0)
+        } else {
+            (q + far, n)
+        })
+    }
+}
+
+// Ring of tube radius minor around a circle of radius major about p,
+// in the plane normal to n.
+pub struct Torus {
+    pub p: Vec3,
+    pub n: Vec3,
+    pub major: f64,
+    pub minor: f64,
+    pub mat: usize,
+    f: Frame,
+}
+
+impl Torus {
+    pub fn new(p: Vec3, n: Vec3, major: f64, minor: f64, mat: usize) -> Torus {
+        let f = Frame::new(p, n);
+        Torus {
+            p,
+            n: f.w,
+            major,
+            minor,
+            mat,
+            f,
+        }
+    }
+
+    // Crossings in increasing order. The quartic is solved from where the
+    // line enters the bounding sphere, which keeps the coefficients small.
+    fn roots(&self, o: Vec3, d: Vec3) -> Vec<f64> {
+        let (big, r) = (self.major, self.minor);
+        let b = o.dot(&d);
+        let disc = b * b - o.dot(&o) + (big + r) * (big + r);
+        if disc < 0.0 {
+            return Vec::new();
+        }
+        let t0 = -b - disc.sqrt();
+        let o = o + d * t0;
+        let f = o.dot(&d);
+        let e = o.dot(&o) + big * big - r * r;
+        let k = 4.0 * big * big;
+        let mut ts: Vec<f64> = quartic(
+            4.0 * f,
+            4.0 * f * f + 2.0 * e - k * (d.x * d.x + d.y * d.y),
+            4.0 * f * e - 2.0 * k * (o.x * d.x + o.y * d.y),
+            e * e - k * (o.x * o.x + o.y * o.y),
+        )
+        .into_iter()
+        .map(|t| t + t0)
+        .collect();
+        ts.sort_by(|a, b| a.total_cmp(b));
+        ts
+    }
+
+    fn hit_at(&self, ray: &Ray, o: Vec3, d: Vec3, t: f64) -> Hit {
+        let q = o + d * t;
+        let r = (q.x * q.x + q.y * q.y).sqrt();
+        let c = Vec3::new(q.x / r * self.major, q.y / r * self.major, 0.0);
+        Hit {
+            t,
+            p: ray.o + ray.d * t,
+            n: self.f.vector((q - c).norm()),
+            uv: (angle(q.x, q.y), angle(r - self.major, q.z)),
+            mat: self.mat,
+        }
+    }
+}
+
+impl Shape for Torus {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        let (o, d) = self.f.local(ray);
+        let t = self.roots(o, d).into_iter().find(|&t| t > EPS)?;
+        if t >= t_max {
+            return None;
+        }
+        Some(self.hit_at(ray, o, d, t))
+    }
+
+    fn bounds(&self) -> Aabb {
+        let b = circle_bounds(self.p, self.n, self.major);
+        let r = Vec3::new(self.minor, self.minor, self.minor);
+        Aabb::from_points(&[b.min - r, b.max + r])
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "torus p={} n={} major={} minor={}",
+            vec_str(self.p),
+            vec_str(self.n),
+            self.major,
+            self.minor
+        )
+    }
+
+    fn solid(&self) -> bool {
+        true
+    }
+
+    // a tangent root that comes out single is dropped
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let (o, d) = self.f.local(ray);
+        self.roots(o, d)
+            .chunks_exact(2)
+            .map(|t| Span {
+                enter: self.hit_at(ray, o, d, t[0]),
+                exit: self.hit_at(ray, o, d, t[1]),
+            })
+            .collect()
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * PI * self.major * self.minor
+    }
+
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        // the outside of the ring has more area than the inside
+        let phi = loop {
+            let phi = 2.0 * PI * random();
+            let w = (self.major + self.minor * phi.cos()) / (self.major + self.minor);
+            if random() < w {
+                break phi;
+            }
+        };
+        let (s, c) = (2.0 * PI * random()).sin_cos();
+        let n = Vec3::new(phi.cos() * c, phi.cos() * s, phi.sin());
+        let q = Vec3::new(self.major * c, self.major * s, 0.0) + n * self.minor;
+        Some((self.f.point(q), self.f.vector(n)))
+    }
+}
+
+// Real roots of t^4 + a t^3 + b t^2 + c t + d by Ferrari's method, each
+// polished with a few Newton steps.
+fn quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    let a2 = a * a;
+    // depressed: y^4 + p y^2 + q y + r with t = y - a/4
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+    let mut ys = Vec::new();
+    if q.abs() < 1e-9 {
+        for z in quadratic(1.0, p / 2.0, r) {
+            if z >= 0.0 {
+                ys.push(z.sqrt());
+                ys.push(-z.sqrt());
+            }
+        }
+    } else {
+        let m = cubic_max(p, p * p / 4.0 - r, -q * q / 8.0);
+        if m <= 0.0 {
+            return Vec::new();
+        }
+        let s = (2.0 * m).sqrt();
+        ys.extend(quadratic(1.0, s / 2.0, p / 2.0 + m - q / (2.0 * s)));
+        ys.extend(quadratic(1.0, -s / 2.0, p / 2.0 + m + q / (2.0 * s)));
+    }
+    ys.into_iter()
+        .map(|y| {
+            let mut t = y - a / 4.0;
+            for _ in 0..3 {
+                let f = (((t + a) * t + b) * t + c) * t + d;
+                let df = ((4.0 * t + 3.0 * a) * t + 2.0 * b) * t + c;
+                if df == 0.0 {
+                    break;
+                }
+                t -= f / df;
+            }
+            t
+        })
+        .collect()
+}
+
+// Largest real root of m^3 + a m^2 + b m + c.
+fn cubic_max(a: f64, b: f64, c: f64) -> f64 {
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let disc = q * q / 4.0 + p * p * p / 27.0;
+    let z = if disc > 0.0 {
+        let s = disc.sqrt();
+        (-q / 2.0 + s).cbrt() + (-q / 2.0 - s).cbrt()
+    } else {
+        let r = (-p / 3.0).sqrt();
+        2.0 * r * ((-q / 2.0 / (r * r * r)).clamp(-1.0, 1.0).acos() / 3.0).cos()
+    };
+    let mut m = z - a / 3.0;
+    for _ in 0..2 {
+        let f = ((m + a) * m + b) * m + c;
+        let df = (3.0 * m + 2.0 * a) * m + b;
+        if df == 0.0 {
+            break;
+        }
+        m -= f / df;
+    }
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed_random;
+
+    fn near(a: Vec3, b: Vec3, tol: f64) -> bool {
+        let d = a - b;
+        d.dot(&d).sqrt() < tol
+    }
+
+    fn shapes() -> Vec<(&'static str, Box<dyn Shape>)> {
+        let tilt = Vec3::new(1.0, 2.0, -0.5);
+        vec![
+            (
+                "disk",
+                Box::new(Disk::new(Vec3::new(1.0, 2.0, 3.0), tilt, 4.0, 0)),
+            ),
+            (
+                "cylinder",
+                Box::new(Cylinder::new(Vec3::zero(), tilt * 2.0, 1.5, 0)),
+            ),
+            (
+                "cone",
+                Box::new(Cone::new(
+                    Vec3::new(5.0, 0.0, 0.0),
+                    Vec3::new(5.0, 6.0, 2.0),
+                    2.0,
+                    0,
+                )),
+            ),
+            (
+                "box",
+                Box::new(Cuboid::new(
+                    Vec3::new(-1.0, 0.0, 2.0),
+                    Vec3::new(2.0, 2.0, 0.0),
+                    Vec3::new(-1.0, 1.0, 0.0),
+                    Vec3::new(0.0, 0.0, 3.0),
+                    0,
+                )),
+            ),
+            (
+                "torus",
+                Box::new(Torus::new(Vec3::new(0.0, 1.0, 0.0), tilt, 3.0, 1.0, 0)),
+            ),
+        ]
+    }
+
+    #[test]
+    fn hits() {
+        let down = |x: f64, y: f64| Ray::new(Vec3::new(x, y, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        let z = Vec3::new(0.0, 0.0, 1.0);
+
+        let disk = Disk::new(Vec3::zero(), z, 2.0, 1);
+        let h = disk.intersect(&down(1.0, 1.0), 1e20).unwrap();
+        assert!((h.t - 10.0).abs() < 1e-12 && h.n.z == 1.0 && h.mat == 1);
+        assert!(disk.intersect(&down(1.5, 1.5), 1e20).is_none());
+        assert!(disk.intersect(&down(1.0, 1.0), 10.0).is_none());
+
+        let cyl = Cylinder::new(Vec3::zero(), z * 4.0, 1.0, 1);
+        let h = cyl.intersect(&down(0.5, 0.0), 1e20).unwrap();
+        assert!((h.t - 6.0).abs() < 1e-12 && near(h.n, z, 1e-12));
+        let side = Ray::new(Vec3::new(-5.0, 0.0, 2.0), Vec3::new(1.0, 0.0, 0.0));
+        let h = cyl.intersect(&side, 1e20).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-12 && near(h.n, Vec3::new(-1.0, 0.0, 0.0), 1e-12));
+        assert!(cyl.intersect(&down(1.1, 0.0), 1e20).is_none());
+
+        // tip at z = 4, radius 2 at the base
+        let cone = Cone::new(Vec3::zero(), z * 4.0, 2.0, 1);
+        let h = cone.intersect(&down(1.0, 0.0), 1e20).unwrap();
+        assert!((h.t - 8.0).abs() < 1e-12);
+        assert!(near(h.n, Vec3::new(2.0, 0.0, 1.0).norm(), 1e-12));
+        let up = Ray::new(Vec3::new(1.0, 0.0, -3.0), z);
+        let h = cone.intersect(&up, 1e20).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-12 && near(h.n, z * -1.0, 1e-12));
+        assert!(cone.intersect(&down(2.1, 0.0), 1e20).is_none());
+
+        let cube = Cuboid::aligned(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0), 1);
+        let h = cube.intersect(&down(0.5, -0.5), 1e20).unwrap();
+        assert!((h.t - 9.0).abs() < 1e-12 && near(h.n, z, 1e-12));
+        assert!((h.uv.0 - 0.75).abs() < 1e-12 && (h.uv.1 - 0.25).abs() < 1e-12);
+        // from inside, the far face
+        let h = cube.intersect(&Ray::new(Vec3::zero(), z), 1e20).unwrap();
+        assert!((h.t - 1.0).abs() < 1e-12 && near(h.n, z, 1e-12));
+
+        // ring of radius 3 and tube 1 in the xy plane
+        let torus = Torus::new(Vec3::zero(), z, 3.0, 1.0, 1);
+        let h = torus.intersect(&down(3.0, 0.0), 1e20).unwrap();
+        assert!((h.t - 9.0).abs() < 1e-9 && near(h.n, z, 1e-9));
+        assert!(torus.intersect(&down(0.0, 0.0), 1e20).is_none());
+        let across = Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let s = torus.spans(&across);
+        let ts: Vec<_> = s.iter().map(|s| (s.enter.t, s.exit.t)).collect();
+        assert_eq!(ts.len(), 2);
+        for (g, w) in ts.iter().zip([(6.0, 8.0), (12.0, 14.0)]) {
+            assert!(
+                (g.0 - w.0).abs() < 1e-9 && (g.1 - w.1).abs() < 1e-9,
+                "{:?}",
+                ts
+            );
+        }
+    }
+
+    #[test]
+    fn areas() {
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let cases: [(Box<dyn Shape>, f64); 5] = [
+            (Box::new(Disk::new(Vec3::zero(), z, 2.0, 0)), 4.0 * PI),
+            (
+                Box::new(Cylinder::new(Vec3::zero(), z * 3.0, 2.0, 0)),
+                20.0 * PI,
+            ),
+            (
+                Box::new(Cone::new(Vec3::zero(), z * 4.0, 3.0, 0)),
+                24.0 * PI,
+            ),
+            (
+                Box::new(Cuboid::aligned(Vec3::zero(), Vec3::new(1.0, 2.0, 3.0), 0)),
+                22.0,
+            ),
+            (
+                Box::new(Torus::new(Vec3::zero(), z, 3.0, 1.0, 0)),
+                12.0 * PI * PI,
+            ),
+        ];
+        for (s, a) in cases {
+            assert!((s.area() - a).abs() < 1e-9, "{}", s.export());
+        }
+    }
+
+    // Every sample lies on the surface it was drawn from, with the normal a
+    // ray coming back along it would find.
+    #[test]
+    fn samples_on_surface() {
+        seed_random(4);
+        for (name, s) in shapes() {
+            for _ in 0..2000 {
+                let (p, n) = s.sample().unwrap();
+                assert!((n.dot(&n) - 1.0).abs() < 1e-9, "{}", name);
+                let h = s
+                    .intersect(&Ray::new(p + n * 0.5, n * -1.0), 1e20)
+                    .unwrap_or_else(|| panic!("{}: missed {:?}", name, (p.x, p.y, p.z)));
+                assert!((h.t - 0.5).abs() < 1e-6, "{}: t = {}", name, h.t);
+                if name != "disk" {
+                    assert!(near(h.n, n, 1e-6), "{}", name);
+                }
+            }
+        }
+    }
+
+    // The share of samples on part of a surface matches its share of the area.
+    #[test]
+    fn samples_uniform() {
+        seed_random(5);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let share = |s: &dyn Shape, part: &dyn Fn(Vec3, Vec3) -> bool| {
+            let n = 40000;
+            let k = (0..n)
+                .filter(|_| {
+                    let (p, nl) = s.sample().unwrap();
+                    part(p, nl)
+                })
+                .count();
+            k as f64 / n as f64
+        };
+        let check = |got: f64, want: f64| assert!((got - want).abs() < 0.01, "{} {}", got, want);
+
+        // inner half of a disk of radius 2
+        let disk = Disk::new(Vec3::zero(), z, 2.0, 0);
+        check(share(&disk, &|p, _| p.dot(&p) < 1.0), 0.25);
+        // caps of a cylinder 3 high and 2 wide
+        let cyl = Cylinder::new(Vec3::zero(), z * 3.0, 2.0, 0);
+        check(share(&cyl, &|_, n| n.z.abs() > 0.5), 8.0 / 20.0);
+        // lower half of the side of a cylinder
+        check(share(&cyl, &|p, n| n.z == 0.0 && p.z < 1.5), 6.0 / 20.0);
+        // base of a cone, and the side below half height, 3/4 of the side
+        let cone = Cone::new(Vec3::zero(), z * 4.0, 3.0, 0);
+        check(share(&cone, &|_, n| n.z < -0.5), 9.0 / 24.0);
+        check(
+            share(&cone, &|p, n| n.z > 0.0 && p.z < 2.0),
+            0.75 * 15.0 / 24.0,
+        );
+        // largest faces of a 1 x 2 x 3 box
+        let cube = Cuboid::aligned(Vec3::zero(), Vec3::new(1.0, 2.0, 3.0), 0);
+        check(share(&cube, &|_, n| n.x.abs() > 0.5), 12.0 / 22.0);
+        check(share(&cube, &|_, n| n.x > 0.5), 6.0 / 22.0);
+        // outer half of a torus
+        let torus = Torus::new(Vec3::zero(), z, 3.0, 1.0, 0);
+        check(
+            share(&torus, &|p, n| n.dot(&Vec3::new(p.x, p.y, 0.0)) > 0.0),
+            (3.0 * PI + 2.0) / (6.0 * PI),
+        );
+    }
+
+    #[test]
+    fn bounds() {
+        seed_random(6);
+        for (name, s) in shapes() {
+            let b = s.bounds();
+            let pad = Vec3::new(1e-9, 1e-9, 1e-9);
+            for _ in 0..2000 {
+                let (p, _) = s.sample().unwrap();
+                let (lo, hi) = (b.min - pad, b.max + pad);
+                assert!(p.x >= lo.x && p.y >= lo.y && p.z >= lo.z, "{}", name);
+                assert!(p.x <= hi.x && p.y <= hi.y && p.z <= hi.z, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn roots() {
+        // (t - 1)(t - 2)(t + 3)(t - 5)
+        let mut ts = quartic(-5.0, -7.0, 41.0, -30.0);
+        ts.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(ts.len(), 4);
+        for (t, w) in ts.iter().zip([-3.0, 1.0, 2.0, 5.0]) {
+            assert!((t - w).abs() < 1e-9, "{:?}", ts);
+        }
+        assert!(quartic(0.0, 0.0, 0.0, 1.0).is_empty());
+        assert_eq!(quadratic(1.0, -1.0, -3.0).len(), 2);
+        assert!(quadratic(1.0, 0.0, 1.0).is_empty());
+    }
+}
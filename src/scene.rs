@@ -0,0 +1,468 @@
+use crate::bvh::{Aabb, Bvh};
+use crate::camera::Camera;
+use crate::csg::{Csg, Op};
+use crate::env::Environment;
+use crate::instance::{Group, Instance};
+use crate::light::{luminance, Light};
+use crate::material::{from_refl, Material, Refl};
+use crate::medium::Medium;
+use crate::shape::{Hit, Plane, Quad, Shape, Sphere};
+use crate::texture::Texture;
+use crate::transform::Transform;
+use crate::{random, transmittance, Ray, Vec3, INF, MAX_CROSSINGS};
+use std::sync::Arc;
+
+// Below this many primitives a linear scan is faster than the BVH (see
+// --bench).
+pub const BVH_MIN_PRIMS: usize = 32;
+
+// weigh_lights() looks at the surfaces seen through a grid of this many
+// camera rays each way
+const PROBES: usize = 16;
+
+pub struct Settings {
+    pub w: usize,
+    pub h: usize,
+    pub samps: usize,
+    pub max_depth: usize,
+    // bounces before Russian roulette may end a path
+    pub rr_depth: usize,
+    pub seed: Option<u64>,
+    // pick lights by what weigh_lights() finds rather than by power
+    pub weigh_lights: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            w: 640,
+            h: 480,
+            samps: 1,
+            max_depth: 127,
+            rr_depth: 5,
+            seed: None,
+            weigh_lights: false,
+        }
+    }
+}
+
+pub struct Scene {
+    pub objects: Vec<Box<dyn Shape>>,
+    // shared geometry, placed with instance()
+    pub groups: Vec<Arc<Group>>,
+    pub materials: Vec<Box<dyn Material>>,
+    // named, for textured materials to share
+    pub textures: Vec<Arc<Texture>>,
+    // per material, for the scene file to refer to it by
+    names: Vec<Option<String>>,
+    // emitting objects
+    pub emissive: Vec<usize>,
+    // the ones sampled at diffuse hits, with the running total of their
+    // weights to pick one by
+    pub lights: Vec<Light>,
+    picks: Vec<f64>,
+    // per material: None if nothing emits with it, Some(true) if only
+    // directly sampled lights do
+    sampled: Vec<Option<bool>>,
+    // what rays that leave the scene see
+    pub env: Option<Box<dyn Environment>>,
+    // filling the space between objects
+    pub fog: Option<Medium>,
+    pub cam: Camera,
+    pub settings: Settings,
+    accel: Option<Accel>,
+}
+
+// BVH over the parts of all bounded objects in a list, for the scene and
+// for groups.
+pub struct Accel {
+    bvh: Bvh,
+    // (object, part) for each BVH item
+    prims: Vec<(usize, usize)>,
+    // planes and the like, tested one by one
+    unbounded: Vec<usize>,
+}
+
+impl Accel {
+    pub fn build(objects: &[Box<dyn Shape>]) -> Accel {
+        let mut prims = Vec::new();
+        let mut bounds: Vec<Aabb> = Vec::new();
+        let mut unbounded = Vec::new();
+        for (i, obj) in objects.iter().enumerate() {
+            if !obj.bounds().is_finite() {
+                unbounded.push(i);
+                continue;
+            }
+            for k in 0..obj.parts() {
+                prims.push((i, k));
+                bounds.push(obj.part_bounds(k));
+            }
+        }
+        Accel {
+            bvh: Bvh::build(&bounds),
+            prims,
+            unbounded,
+        }
+    }
+
+    // objects must be the list the BVH was built over
+    pub fn intersect(&self, objects: &[Box<dyn Shape>], r: &Ray, t_max: f64) -> Option<Hit> {
+        let mut hit = closest(self.unbounded.iter().map(|&i| &*objects[i]), r, t_max);
+        let t_max = hit.as_ref().map_or(t_max, |h| h.t);
+        let h = self.bvh.intersect(r, t_max, |k, t| {
+            let (i, part) = self.prims[k];
+            objects[i].intersect_part(part, r, t)
+        });
+        if h.is_some() {
+            hit = h;
+        }
+        hit
+    }
+}
+
+impl Scene {
+    pub fn new(cam: Camera) -> Scene {
+        Scene {
+            objects: Vec::new(),
+            groups: Vec::new(),
+            materials: Vec::new(),
+            textures: Vec::new(),
+            names: Vec::new(),
+            emissive: Vec::new(),
+            lights: Vec::new(),
+            picks: Vec::new(),
+            sampled: Vec::new(),
+            env: None,
+            fog: None,
+            cam,
+            settings: Settings::default(),
+            accel: None,
+        }
+    }
+
+    pub fn material(&mut self, m: impl Material + 'static) -> usize {
+        self.material_boxed(Box::new(m))
+    }
+
+    pub fn material_boxed(&mut self, m: Box<dyn Material>) -> usize {
+        self.materials.push(m);
+        self.names.push(None);
+        self.sampled.push(None);
+        self.materials.len() - 1
+    }
+
+    // Names material m; false if the name is taken.
+    pub fn name_material(&mut self, m: usize, name: &str) -> bool {
+        if self.find_material(name).is_some() {
+            return false;
+        }
+        self.names[m] = Some(name.to_string());
+        true
+    }
+
+    pub fn find_material(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n.as_deref() == Some(name))
+    }
+
+    pub fn material_name(&self, m: usize) -> Option<&str> {
+        self.names[m].as_deref()
+    }
+
+    pub fn add(&mut self, obj: impl Shape + 'static) {
+        self.add_boxed(Box::new(obj));
+    }
+
+    pub fn add_boxed(&mut self, obj: Box<dyn Shape>) {
+        if let Some(m) = obj.material() {
+            let e = self.materials[m].emitted();
+            if e.dot(&e) > 0.0 {
+                self.add_light(&*obj, m, e);
+            }
+        }
+        self.objects.push(obj);
+        self.accel = None;
+    }
+
+    // A material shared with a light that can't be sampled has to be found
+    // by bouncing, so none of its lights are sampled.
+    fn add_light(&mut self, obj: &dyn Shape, m: usize, e: Vec3) {
+        let i = self.objects.len();
+        self.emissive.push(i);
+        match (self.sampled[m], Light::new(i, obj, e)) {
+            (None | Some(true), Some(light)) => {
+                let total = self.picks.last().map_or(0.0, |&p| p);
+                self.picks.push(total + light.weight);
+                self.lights.push(light);
+                self.sampled[m] = Some(true);
+            }
+            (Some(true), None) => {
+                let objects = &self.objects;
+                self.lights.retain(|l| objects[l.obj].material() != Some(m));
+                self.total_picks();
+                self.sampled[m] = Some(false);
+            }
+            _ => self.sampled[m] = Some(false),
+        }
+    }
+
+    fn total_picks(&mut self) {
+        let mut total = 0.0;
+        self.picks = self
+            .lights
+            .iter()
+            .map(|l| {
+                total += l.weight;
+                total
+            })
+            .collect();
+    }
+
+    // A sampled light picked in proportion to its weight.
+    pub fn pick_light(&self) -> Option<&Light> {
+        let total = *self.picks.last()?;
+        let x = random() * total;
+        let i = self.picks.partition_point(|&p| p <= x);
+        Some(&self.lights[i.min(self.lights.len() - 1)])
+    }
+
+    // chance of pick_light() returning l
+    pub fn light_prob(&self, l: &Light) -> f64 {
+        l.weight / self.picks.last().map_or(1.0, |&p| p)
+    }
+
+    // Weighs the lights by the light each one gets, through whatever is in
+    // the way, to the surfaces seen through a coarse grid of camera rays,
+    // rather than by power alone: a big emitter buried under the ground or a
+    // dome round the whole scene has plenty of power but lights little of
+    // what is seen. A tenth of the picks stay spread evenly, for the lights
+    // the grid misses.
+    pub fn weigh_lights(&mut self) {
+        let basis = self.cam.basis(self.settings.w, self.settings.h);
+        let mut got = vec![0.0; self.lights.len()];
+        for k in 0..PROBES * PROBES {
+            let u = ((k % PROBES) as f64 + 0.5) / PROBES as f64 - 0.5;
+            let v = ((k / PROBES) as f64 + 0.5) / PROBES as f64 - 0.5;
+            let mut r = match self.cam.ray(&basis, u, v) {
+                Some(r) => r,
+                None => continue,
+            };
+            // on past the edges of volumes to the first surface
+            let mut medium = self.fog.as_ref();
+            let mut hit = None;
+            for _ in 0..=MAX_CROSSINGS {
+                let h = match self.intersect(&r, INF) {
+                    Some(h) => h,
+                    None => break,
+                };
+                let mat = &*self.materials[h.mat];
+                if !mat.clear() {
+                    hit = Some(h);
+                    break;
+                }
+                medium = if h.n.dot(&r.d) < 0.0 {
+                    mat.medium()
+                } else {
+                    self.fog.as_ref()
+                };
+                r = Ray::new(h.p, r.d);
+            }
+            let hit = match hit {
+                Some(h) => h,
+                None => continue,
+            };
+            // the side the camera sees
+            let nl = if hit.n.dot(&r.d) < 0.0 {
+                hit.n
+            } else {
+                hit.n * -1.0
+            };
+            for (l, g) in self.lights.iter().zip(got.iter_mut()) {
+                let obj = &*self.objects[l.obj];
+                let e = luminance(self.materials[obj.material().unwrap()].emitted());
+                let (wi, pdf, t) = match l.sample(obj, hit.p) {
+                    Some(s) => s,
+                    None => continue,
+                };
+                let cos = nl.dot(&wi);
+                if cos > 0.0 {
+                    let tr = transmittance(self, hit.p, wi, t, medium);
+                    *g += e * cos * luminance(tr) / pdf;
+                }
+            }
+        }
+        let total: f64 = got.iter().sum();
+        if total <= 0.0 {
+            return;
+        }
+        let n = self.lights.len() as f64;
+        for (l, g) in self.lights.iter_mut().zip(got) {
+            l.weight = 0.9 * g / total + 0.1 / n;
+        }
+        self.total_picks();
+    }
+
+    // Whether light from material m reaches diffuse surfaces through
+    // direct sampling, and must not be counted again when a bounce hits it.
+    pub fn sampled(&self, m: usize) -> bool {
+        self.sampled[m] == Some(true)
+    }
+
+    // Registers objects as a named group to be placed with instance().
+    pub fn group(&mut self, name: &str, objects: Vec<Box<dyn Shape>>) -> Arc<Group> {
+        let g = Arc::new(Group::new(name, objects));
+        self.groups.push(g.clone());
+        g
+    }
+
+    pub fn texture(&mut self, t: Texture) -> Arc<Texture> {
+        let t = Arc::new(t);
+        self.textures.push(t.clone());
+        t
+    }
+
+    pub fn instance(&mut self, obj: Arc<dyn Shape>, xf: Transform) {
+        self.add(Instance::new(obj, xf));
+    }
+
+    pub fn csg(&mut self, op: Op, a: Arc<Group>, b: Arc<Group>) {
+        self.add(Csg::new(op, a, b));
+    }
+
+    // Until this is called (and after every add) intersect() tests every
+    // object in turn.
+    pub fn build_bvh(&mut self) {
+        self.accel = Some(Accel::build(&self.objects));
+    }
+
+    // objects, counting each triangle of a mesh
+    pub fn prims(&self) -> usize {
+        self.objects.iter().map(|o| o.parts()).sum()
+    }
+
+    // BVH primitives and nodes, if built
+    pub fn bvh_size(&self) -> Option<(usize, usize)> {
+        self.accel.as_ref().map(|a| (a.prims.len(), a.bvh.nodes()))
+    }
+
+    // Closest hit before t_max; shadow rays pass the distance to the light.
+    pub fn intersect(&self, r: &Ray, t_max: f64) -> Option<Hit> {
+        match &self.accel {
+            Some(a) => a.intersect(&self.objects, r, t_max),
+            None => closest(self.objects.iter().map(|o| &**o), r, t_max),
+        }
+    }
+
+    // Shorthands that give each shape a material of its own.
+    pub fn sphere(&mut self, rad: f64, p: Vec3, e: Vec3, c: Vec3, refl: Refl) {
+        let m = self.material_boxed(from_refl(e, c, refl));
+        self.add(Sphere::new(rad, p, m));
+    }
+
+    pub fn plane(&mut self, p: Vec3, n: Vec3, e: Vec3, c: Vec3, refl: Refl) {
+        let m = self.material_boxed(from_refl(e, c, refl));
+        self.add(Plane::new(p, n, m));
+    }
+
+    pub fn quad(&mut self, p: Vec3, u: Vec3, v: Vec3, e: Vec3, c: Vec3, refl: Refl) {
+        let m = self.material_boxed(from_refl(e, c, refl));
+        self.add(Quad::new(p, u, v, m));
+    }
+}
+
+pub fn closest<'a>(objs: impl Iterator<Item = &'a dyn Shape>, r: &Ray, t_max: f64) -> Option<Hit> {
+    let mut hit: Option<Hit> = None;
+    for obj in objs {
+        let t_max = hit.as_ref().map_or(t_max, |h| h.t);
+        if let Some(h) = obj.intersect(r, t_max) {
+            hit = Some(h);
+        }
+    }
+    hit
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene::new(Camera::smallpt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed_random;
+    use std::f64::consts::PI;
+
+    fn white() -> Vec3 {
+        Vec3::new(1.0, 1.0, 1.0)
+    }
+
+    // Lights of power 1, 4 and 16 over a glowing plane that can't be one.
+    fn lit() -> Scene {
+        let mut s = Scene::default();
+        s.plane(
+            Vec3::zero(),
+            Vec3::new(0.0, 1.0, 0.0),
+            white() * 0.1,
+            white(),
+            Refl::Diff,
+        );
+        for (k, rad) in [0.5, 1.0, 2.0].into_iter().enumerate() {
+            let p = Vec3::new(10.0 * k as f64, 20.0, 0.0);
+            s.sphere(
+                rad,
+                p,
+                white() * (1.0 / (PI * PI)),
+                Vec3::zero(),
+                Refl::Diff,
+            );
+        }
+        s
+    }
+
+    #[test]
+    fn picks() {
+        seed_random(9);
+        let s = lit();
+        assert_eq!(s.lights.len(), 3);
+        let total: f64 = s.lights.iter().map(|l| s.light_prob(l)).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+        for (l, want) in s.lights.iter().zip([1.0, 4.0, 16.0]) {
+            assert!((l.power - want).abs() < 1e-9, "{}", l.power);
+            assert!((s.light_prob(l) - want / 21.0).abs() < 1e-12);
+        }
+        let n = 100000;
+        let mut count = [0; 3];
+        for _ in 0..n {
+            count[s.pick_light().unwrap().obj - 1] += 1;
+        }
+        for (k, want) in count.iter().zip([1.0, 4.0, 16.0]) {
+            assert!(
+                (*k as f64 / n as f64 - want / 21.0).abs() < 0.005,
+                "{:?}",
+                count
+            );
+        }
+        assert!(Scene::default().pick_light().is_none());
+    }
+
+    #[test]
+    fn weighed() {
+        // the biggest light is buried under the ground, out of sight
+        let mut s = lit();
+        s.cam = Camera::look_at(
+            Vec3::new(10.0, 10.0, 60.0),
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+        );
+        s.objects[3] = Box::new(Sphere::new(2.0, Vec3::new(20.0, -20.0, 0.0), 3));
+        s.weigh_lights();
+        let p: Vec<f64> = s.lights.iter().map(|l| s.light_prob(l)).collect();
+        assert!((p.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        // only the tenth spread evenly is left for it
+        assert!((p[2] - 0.1 / 3.0).abs() < 1e-12, "{:?}", p);
+        assert!(p[1] > p[0] && p[0] > p[2], "{:?}", p);
+        // but it keeps its power
+        assert!((s.lights[2].power - 16.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,1065 @@
+// Text scene files.
+//
+// One directive per line, `#` starts a comment:
+//
+//   settings w=640 h=480 spp=1 max_depth=127 rr_depth=5
+//   camera   o=50,52,168 target=50,40.8,0 up=0,1,0 fov=48 near=0
+//   environment file=maps/sky.hdr rotate=90 intensity=1
+//   sky      elevation=30 azimuth=-40 turbidity=3
+//   fog      scatter=0.002,0.002,0.002 g=0.3
+//   texture  name=grass type=noise a=0.01,0.15,0.01 b=0.02,0.3,0.02 scale=48
+//   material name=water c=0.9,0.95,1 refl=refr ior=1.33 absorb=0.04,0.01,0
+//   sphere   rad=14 p=25,14,60 mat=water
+//   sphere   rad=10 p=70,40,30 refl=volume scatter=0.3,0.3,0.3 g=0.8
+//   sphere   rad=16.5 p=27,16.5,47 e=0,0,0 c=0.999,0.999,0.999 refl=spec
+//   plane    p=0,0,0 n=0,1,0 c=0.75,0.75,0.75
+//   quad     p=35,81.5,60 u=30,0,0 v=0,0,30 e=12,12,12
+//   triangle v0=20,0,60 v1=40,0,60 v2=30,20,60 c=0.75,0.75,0.75
+//   disk     p=50,85.5,80 n=0,-1,0 rad=15 e=12,12,12
+//   cylinder p0=20,0,60 p1=20,30,60 rad=2
+//   cone     p0=70,0,50 p1=70,25,50 rad=8
+//   box      min=10,0,40 max=40,3,80
+//   box      p=60,0,30 u=20,0,10 v=0,10,0 w=-5,0,10
+//   torus    p=50,10,90 n=0,1,0 major=8 minor=2
+//   mesh     file=models/icosphere.obj scale=12 rotate=0,30,0 translate=70,12,60
+//   group    name=tree
+//     sphere rad=4 p=0,3.6,0 c=0.13,0.066,0.033
+//     sphere rad=16 p=0,21.6,0 c=0.0588,0.361,0.0941
+//   end
+//   instance group=tree scale=0.8 rotate=0,45,0 translate=50,0,47
+//   csg      op=difference a=hut b=door
+//
+// A plane is infinite, through `p` with normal `n`. A quad is the
+// parallelogram with corner `p` and edges `u` and `v`. A mesh is read from a
+// Wavefront OBJ file, relative to the scene file, then scaled (one number or
+// x,y,z), rotated about x, y and z (degrees) and translated. `e`, `c` default
+// to zero and `refl` to diff.
+//
+// `refl=metal` and `refl=frosted` are rough versions of spec and refr, with
+// GGX roughness `rough` from 0 (polished) to 1. A metal reflects `c`, or
+// with `metal=gold`, copper, aluminium or silver the colour of that metal
+// tinted by `c` (default white). Refr and frosted take an index of
+// refraction `ior` (default 1.5) and `absorb`, the fraction of red, green
+// and blue light absorbed per unit of distance inside. Given `scatter` as
+// well, the inside scatters light the same way as a volume.
+//
+// A `material` line names the material fields on it, and any shape can
+// then use them with `mat=` instead of giving its own.
+//
+// A `texture` line names a colour that varies over a surface, which a
+// material then multiplies its own `c` by with `tex=` (c defaults to white
+// when it has one). `type=image` reads `file`, relative to the scene file;
+// checker, noise and gradient go from colour `a` to `b`, checker in squares,
+// noise as fractal value noise and gradient from the bottom (v = 0) to the
+// top. `scale` repeats the pattern that many times per unit of uv. Spheres
+// are mapped by longitude and latitude, quads from 0 to 1 along their
+// edges, planes in scene units and meshes by their OBJ texture coordinates.
+//
+// A disk has centre `p`, normal `n` and radius `rad`. Cylinders run from
+// `p0` to `p1` and are closed at both ends; a cone has its base about `p0`
+// and its tip at `p1`. A box is given by opposite corners `min` and `max`, or
+// by a corner `p` and perpendicular edges `u`, `v` and `w`. A torus lies
+// about `p` in the plane normal to `n`, `major` being the ring's radius and
+// `minor` the tube's.
+//
+// Any shape takes `scale`, `rotate` and `translate` the same way, so a
+// squashed sphere is an ellipsoid. Shapes between `group` and `end` are not
+// placed themselves; they make up a named group that `instance` lines place
+// any number of times without copying it. A group may hold instances of
+// groups defined before it.
+//
+// `csg` combines two groups as solids by `op`: union, intersection or
+// difference (a minus b). Only spheres, planes (the half-space behind the
+// normal), cylinders, cones, boxes, tori, instances of those and other csg
+// lines have an inside. The
+// surfaces cut out of a by b take a's material.
+//
+// The camera looks at `target`, or along `d` instead; `up` defaults to +y,
+// `fov` (vertical, degrees) to the smallpt camera's and `near` to 0. A
+// nonzero `aperture` (lens radius) gives depth of field, focused at distance
+// `focus` (default: on the target). `projection` is perspective (default),
+// ortho, fisheye or equirect.
+//
+// `environment` lights the scene from all around with an equirectangular
+// image (Radiance .hdr or OpenEXR; 8-bit images are taken as sRGB), seen by
+// every ray that leaves the scene and sampled by its brightness at diffuse
+// hits. The top row is straight up and the middle of the image lies along
+// -z; `rotate` turns it about +y by that many degrees and `intensity`
+// (default 1) scales it.
+//
+// `sky` is the alternative to an image: the Preetham analytic daylight sky
+// with the sun `elevation` degrees above the horizon (default 45) and
+// `azimuth` degrees round from -z towards +x (default 0), hazier as
+// `turbidity` goes from 2 to 10 (default 3), and a sun disk of the colour
+// that comes through that much air. `intensity` scales both; at 1 a white
+// surface in high sun comes out about white. Below the horizon the sky is
+// black. A scene has at most one environment or sky.
+//
+// `refl=volume` makes a closed shape with no surface, only a medium inside
+// that absorbs `absorb` and scatters `scatter` of red, green and blue light
+// per unit of distance, mostly forwards as `g` goes towards 1 and back
+// towards -1 (default 0, evenly). `fog` fills the rest of the scene with
+// such a medium; there is at most one. Volumes and glass in fog hold their
+// own medium, not the fog, and don't nest.
+//
+// Numbers are written with Rust's shortest round-trip formatting, so save
+// followed by load gives back the same scene. Files are saved by their
+// absolute paths, so the saved scene loads from any directory.
+
+use crate::camera::{Camera, Projection, SMALLPT_FOV};
+use crate::csg::{Csg, Op};
+use crate::env::{EnvMap, Environment};
+use crate::instance::{Group, Instance};
+use crate::material::{Diffuse, Glass, Material, Mirror, Textured, GLASS};
+use crate::medium::{Filled, Medium, Volume};
+use crate::mesh::{Mesh, Triangle};
+use crate::microfacet::{Frosted, Metal, RoughMetal};
+use crate::prim::{Cone, Cuboid, Cylinder, Disk, Torus};
+use crate::scene::Scene;
+use crate::shape::{Plane, Quad, Shape, Sphere};
+use crate::sky::Sky;
+use crate::texture::{Kind, Texture};
+use crate::transform::Transform;
+use crate::Vec3;
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.msg)
+        } else {
+            write!(f, "line {}: {}", self.line, self.msg)
+        }
+    }
+}
+
+pub fn load(path: &str) -> Result<Scene, ParseError> {
+    let src = fs::read_to_string(path).map_err(|e| ParseError {
+        line: 0,
+        msg: e.to_string(),
+    })?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    parse(&src, dir)
+}
+
+pub fn save(scene: &Scene, path: &str) -> std::io::Result<()> {
+    fs::write(path, to_string(scene))
+}
+
+pub fn to_string(scene: &Scene) -> String {
+    let mut out = String::new();
+    let s = &scene.settings;
+    writeln!(
+        out,
+        "settings w={} h={} spp={} max_depth={} rr_depth={}",
+        s.w, s.h, s.samps, s.max_depth, s.rr_depth
+    )
+    .unwrap();
+    let cam = &scene.cam;
+    writeln!(
+        out,
+        "camera o={} target={} up={} fov={} near={} aperture={} focus={} projection={}",
+        vec_str(cam.o),
+        vec_str(cam.target),
+        vec_str(cam.up),
+        cam.fov,
+        cam.near,
+        cam.aperture,
+        cam.focus,
+        cam.projection.name()
+    )
+    .unwrap();
+    if let Some(env) = &scene.env {
+        writeln!(out, "{}", env.export()).unwrap();
+    }
+    if let Some(fog) = &scene.fog {
+        writeln!(out, "fog {}", fog.export()).unwrap();
+    }
+    for t in &scene.textures {
+        writeln!(out, "texture name={} {}", t.name, t.export()).unwrap();
+    }
+    for (m, mat) in scene.materials.iter().enumerate() {
+        if let Some(name) = scene.material_name(m) {
+            writeln!(out, "material name={} {}", name, mat.export()).unwrap();
+        }
+    }
+    for g in &scene.groups {
+        writeln!(out, "group name={}", g.name).unwrap();
+        for obj in &g.objects {
+            write_object(&mut out, scene, &**obj, "  ");
+        }
+        writeln!(out, "end").unwrap();
+    }
+    for obj in &scene.objects {
+        write_object(&mut out, scene, &**obj, "");
+    }
+    out
+}
+
+fn write_object(out: &mut String, scene: &Scene, obj: &dyn Shape, indent: &str) {
+    match obj.material() {
+        Some(m) => match scene.material_name(m) {
+            Some(name) => writeln!(out, "{}{} mat={}", indent, obj.export(), name),
+            None => writeln!(
+                out,
+                "{}{} {}",
+                indent,
+                obj.export(),
+                scene.materials[m].export()
+            ),
+        },
+        None => writeln!(out, "{}{}", indent, obj.export()),
+    }
+    .unwrap();
+}
+
+// A file named in a scene, relative to the scene's directory, as an
+// absolute path, so that the scene can be saved anywhere and still find it.
+pub fn resolve(file: &str, dir: &Path) -> Result<String, String> {
+    let path = dir.join(file);
+    fs::canonicalize(&path)
+        .map(|p| p.display().to_string())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn vec_str(v: Vec3) -> String {
+    format!("{},{},{}", v.x, v.y, v.z)
+}
+
+// Mesh files are looked up relative to dir.
+pub fn parse(src: &str, dir: &Path) -> Result<Scene, ParseError> {
+    let mut scene = Scene::default();
+    let mut group = None;
+    for (i, raw) in src.lines().enumerate() {
+        let line = raw.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        parse_line(&mut scene, &mut group, i + 1, line, dir)
+            .map_err(|msg| ParseError { line: i + 1, msg })?;
+    }
+    if let Some(g) = group {
+        return Err(ParseError {
+            line: g.line,
+            msg: format!("group: '{}' has no 'end'", g.name),
+        });
+    }
+    Ok(scene)
+}
+
+// A group being read, between `group` and `end`.
+struct Open {
+    line: usize,
+    name: String,
+    objects: Vec<Box<dyn Shape>>,
+}
+
+// Line n adds to the open group if there is one.
+fn parse_line(
+    scene: &mut Scene,
+    group: &mut Option<Open>,
+    n: usize,
+    line: &str,
+    dir: &Path,
+) -> Result<(), String> {
+    let mut words = line.split_whitespace();
+    let kind = words.next().unwrap();
+    let fields = Fields::new(kind, words)?;
+    let obj: Box<dyn Shape> = match kind {
+        "settings" | "camera" | "environment" | "sky" | "fog" if group.is_some() => {
+            return Err(format!("{}: not allowed inside a group", kind));
+        }
+        "settings" => {
+            fields.check(&["w", "h", "spp", "max_depth", "rr_depth"])?;
+            let s = &mut scene.settings;
+            s.w = fields.opt("w", s.w, parse_count)?;
+            s.h = fields.opt("h", s.h, parse_count)?;
+            s.samps = fields.opt("spp", s.samps, parse_count)?;
+            s.max_depth = fields.opt("max_depth", s.max_depth, parse_count)?;
+            s.rr_depth = fields.opt("rr_depth", s.rr_depth, parse_usize)?;
+            return Ok(());
+        }
+        "camera" => {
+            fields.check(&[
+                "o",
+                "target",
+                "d",
+                "up",
+                "fov",
+                "near",
+                "aperture",
+                "focus",
+                "projection",
+            ])?;
+            let o = fields.req("o", parse_vec)?;
+            let target = match fields.get("d") {
+                Some(_) if fields.get("target").is_some() => {
+                    return Err("camera: give either 'target' or 'd', not both".to_string());
+                }
+                Some(_) => o + fields.req("d", parse_vec)?,
+                None => fields.req("target", parse_vec)?,
+            };
+            if (target - o).dot(&(target - o)) == 0.0 {
+                return Err("camera: target must differ from o".to_string());
+            }
+            let projection = fields.opt("projection", Projection::Perspective, parse_projection)?;
+            let fov = fields.opt("fov", SMALLPT_FOV, parse_f64)?;
+            let max_fov = if projection == Projection::Fisheye {
+                360.0
+            } else {
+                180.0
+            };
+            if !(fov > 0.0 && fov < max_fov) {
+                return Err(format!(
+                    "camera: field 'fov': {} is not between 0 and {}",
+                    fov, max_fov
+                ));
+            }
+            let up = fields.opt("up", Vec3::new(0.0, 1.0, 0.0), parse_vec)?;
+            let aperture = fields.opt("aperture", 0.0, parse_f64)?;
+            let focus = fields.opt("focus", 0.0, parse_f64)?;
+            if aperture < 0.0 || focus < 0.0 {
+                return Err("camera: aperture and focus must not be negative".to_string());
+            }
+            scene.cam = Camera {
+                near: fields.opt("near", 0.0, parse_f64)?,
+                aperture,
+                focus,
+                projection,
+                ..Camera::look_at(o, target, up, fov)
+            };
+            return Ok(());
+        }
+        "environment" => {
+            fields.check(&["file", "rotate", "intensity"])?;
+            let file = fields.req("file", |s| Ok(s.to_string()))?;
+            let rotate = fields.opt("rotate", 0.0, parse_f64)?;
+            let intensity = fields.opt("intensity", 1.0, parse_intensity)?;
+            let env = EnvMap::load(&file, dir, rotate, intensity)
+                .map_err(|e| format!("environment: {}", e))?;
+            return set_env(scene, &fields, Box::new(env));
+        }
+        "sky" => {
+            fields.check(&["elevation", "azimuth", "turbidity", "intensity"])?;
+            let elevation = fields.opt("elevation", 45.0, parse_elevation)?;
+            let azimuth = fields.opt("azimuth", 0.0, parse_f64)?;
+            let turbidity = fields.opt("turbidity", 3.0, parse_turbidity)?;
+            let intensity = fields.opt("intensity", 1.0, parse_intensity)?;
+            let sky = Sky::new(elevation, azimuth, turbidity, intensity);
+            return set_env(scene, &fields, Box::new(sky));
+        }
+        "fog" => {
+            fields.check(&["absorb", "scatter", "g"])?;
+            if scene.fog.is_some() {
+                return Err("fog: the fog is already given".to_string());
+            }
+            scene.fog = Some(medium(&fields, Vec3::zero())?);
+            return Ok(());
+        }
+        "material" => {
+            let mut known = vec!["name"];
+            known.extend(MATERIAL_FIELDS);
+            fields.check(&known)?;
+            let name = fields.req("name", |s| Ok(s.to_string()))?;
+            let m = new_material(scene, &fields)?;
+            let m = scene.material_boxed(m);
+            if !scene.name_material(m, &name) {
+                return Err(format!("material: '{}' is already defined", name));
+            }
+            return Ok(());
+        }
+        "texture" => {
+            fields.check(&["name", "type", "file", "a", "b", "scale"])?;
+            let name = fields.req("name", |s| Ok(s.to_string()))?;
+            if scene.textures.iter().any(|t| t.name == name) {
+                return Err(format!("texture: '{}' is already defined", name));
+            }
+            let scale = fields.opt("scale", 1.0, parse_positive)?;
+            let kind = fields.req("type", parse_texture)?;
+            let not: &[&str] = if kind == "image" {
+                &["a", "b"]
+            } else {
+                &["file"]
+            };
+            if let Some(k) = not.iter().find(|k| fields.get(k).is_some()) {
+                return Err(format!("texture: '{}' can't be used with type={}", k, kind));
+            }
+            let t = match kind {
+                "image" => {
+                    let file = fields.req("file", |s| Ok(s.to_string()))?;
+                    Texture::image(&name, scale, &file, dir)
+                        .map_err(|e| format!("texture: {}", e))?
+                }
+                _ => {
+                    let a = fields.req("a", parse_vec)?;
+                    let b = fields.req("b", parse_vec)?;
+                    let kind = match kind {
+                        "checker" => Kind::Checker(a, b),
+                        "noise" => Kind::Noise(a, b),
+                        _ => Kind::Gradient(a, b),
+                    };
+                    Texture::new(&name, scale, kind)
+                }
+            };
+            scene.texture(t);
+            return Ok(());
+        }
+        "group" => {
+            fields.check(&["name"])?;
+            if let Some(g) = group {
+                return Err(format!("group: '{}' has no 'end' yet", g.name));
+            }
+            let name = fields.req("name", |s| Ok(s.to_string()))?;
+            if scene.groups.iter().any(|g| g.name == name) {
+                return Err(format!("group: '{}' is already defined", name));
+            }
+            *group = Some(Open {
+                line: n,
+                name,
+                objects: Vec::new(),
+            });
+            return Ok(());
+        }
+        "end" => {
+            fields.check(&[])?;
+            let g = group.take().ok_or("end: no group to end")?;
+            if g.objects.is_empty() {
+                return Err(format!("end: group '{}' is empty", g.name));
+            }
+            scene.group(&g.name, g.objects);
+            return Ok(());
+        }
+        "instance" => {
+            fields.check(&["group", "scale", "rotate", "translate"])?;
+            let g = find_group(scene, &fields, "group")?;
+            Box::new(Instance::new(g, transform(&fields)?))
+        }
+        "csg" => {
+            fields.check(&["op", "a", "b", "scale", "rotate", "translate"])?;
+            let op = fields.req("op", parse_op)?;
+            let a = find_group(scene, &fields, "a")?;
+            let b = find_group(scene, &fields, "b")?;
+            if let Some(g) = [&a, &b].into_iter().find(|g| !g.solid()) {
+                return Err(format!(
+                    "csg: group '{}' has shapes without an inside",
+                    g.name
+                ));
+            }
+            place(&fields, Csg::new(op, a, b))?
+        }
+        "sphere" => {
+            fields.check_shape(&["rad", "p"])?;
+            let rad = fields.req("rad", parse_positive)?;
+            let p = fields.req("p", parse_vec)?;
+            let m = material(scene, &fields)?;
+            place(&fields, Sphere::new(rad, p, m))?
+        }
+        "plane" => {
+            fields.check_shape(&["p", "n"])?;
+            let p = fields.req("p", parse_vec)?;
+            let n = fields.req("n", parse_vec)?;
+            if n.dot(&n) == 0.0 {
+                return Err("plane: field 'n' must not be zero".to_string());
+            }
+            let m = material(scene, &fields)?;
+            place(&fields, Plane::new(p, n, m))?
+        }
+        "quad" => {
+            fields.check_shape(&["p", "u", "v"])?;
+            let p = fields.req("p", parse_vec)?;
+            let u = fields.req("u", parse_vec)?;
+            let v = fields.req("v", parse_vec)?;
+            if (u % v).dot(&(u % v)) == 0.0 {
+                return Err("quad: edges 'u' and 'v' must not be parallel".to_string());
+            }
+            let m = material(scene, &fields)?;
+            place(&fields, Quad::new(p, u, v, m))?
+        }
+        "triangle" => {
+            fields.check_shape(&["v0", "v1", "v2"])?;
+            let a = fields.req("v0", parse_vec)?;
+            let b = fields.req("v1", parse_vec)?;
+            let c = fields.req("v2", parse_vec)?;
+            if ((b - a) % (c - a)).dot(&((b - a) % (c - a))) == 0.0 {
+                return Err("triangle: vertices must not be collinear".to_string());
+            }
+            let m = material(scene, &fields)?;
+            place(&fields, Triangle::new(a, b, c, m))?
+        }
+        "disk" => {
+            fields.check_shape(&["p", "n", "rad"])?;
+            let p = fields.req("p", parse_vec)?;
+            let n = fields.req("n", parse_vec)?;
+            if n.dot(&n) == 0.0 {
+                return Err("disk: field 'n' must not be zero".to_string());
+            }
+            let rad = fields.req("rad", parse_positive)?;
+            let m = material(scene, &fields)?;
+            place(&fields, Disk::new(p, n, rad, m))?
+        }
+        "cylinder" | "cone" => {
+            fields.check_shape(&["p0", "p1", "rad"])?;
+            let p0 = fields.req("p0", parse_vec)?;
+            let p1 = fields.req("p1", parse_vec)?;
+            if (p1 - p0).dot(&(p1 - p0)) == 0.0 {
+                return Err(format!("{}: 'p0' and 'p1' must differ", kind));
+            }
+            let rad = fields.req("rad", parse_positive)?;
+            let m = material(scene, &fields)?;
+            if kind == "cylinder" {
+                place(&fields, Cylinder::new(p0, p1, rad, m))?
+            } else {
+                place(&fields, Cone::new(p0, p1, rad, m))?
+            }
+        }
+        "box" => {
+            fields.check_shape(&["min", "max", "p", "u", "v", "w"])?;
+            let aligned = fields.get("min").is_some() || fields.get("max").is_some();
+            let b = if aligned {
+                if ["p", "u", "v", "w"].iter().any(|k| fields.get(k).is_some()) {
+                    return Err(
+                        "box: give either 'min' and 'max' or 'p', 'u', 'v' and 'w'".to_string()
+                    );
+                }
+                let min = fields.req("min", parse_vec)?;
+                let max = fields.req("max", parse_vec)?;
+                if !(min.x < max.x && min.y < max.y && min.z < max.z) {
+                    return Err("box: 'min' must be below 'max' on every axis".to_string());
+                }
+                Cuboid::aligned(min, max, material(scene, &fields)?)
+            } else {
+                let p = fields.req("p", parse_vec)?;
+                let u = fields.req("u", parse_vec)?;
+                let v = fields.req("v", parse_vec)?;
+                let w = fields.req("w", parse_vec)?;
+                let perp = |a: Vec3, b: Vec3| {
+                    let d = a.dot(&b);
+                    d * d <= 1e-18 * a.dot(&a) * b.dot(&b)
+                };
+                if [u, v, w].iter().any(|e| e.dot(e) == 0.0)
+                    || !(perp(u, v) && perp(v, w) && perp(w, u))
+                {
+                    return Err(
+                        "box: edges 'u', 'v' and 'w' must be nonzero and perpendicular".to_string(),
+                    );
+                }
+                Cuboid::new(p, u, v, w, material(scene, &fields)?)
+            };
+            place(&fields, b)?
+        }
+        "torus" => {
+            fields.check_shape(&["p", "n", "major", "minor"])?;
+            let p = fields.req("p", parse_vec)?;
+            let n = fields.req("n", parse_vec)?;
+            if n.dot(&n) == 0.0 {
+                return Err("torus: field 'n' must not be zero".to_string());
+            }
+            let major = fields.req("major", parse_positive)?;
+            let minor = fields.req("minor", parse_positive)?;
+            if minor >= major {
+                return Err("torus: 'minor' must be smaller than 'major'".to_string());
+            }
+            let m = material(scene, &fields)?;
+            place(&fields, Torus::new(p, n, major, minor, m))?
+        }
+        // the transform is applied to the vertices as they are read
+        "mesh" => {
+            fields.check_shape(&["file"])?;
+            let file = fields.req("file", |s| Ok(s.to_string()))?;
+            let xf = transform(&fields)?;
+            let m = material(scene, &fields)?;
+            let mesh = Mesh::load(&file, dir, xf, m).map_err(|e| format!("mesh: {}", e))?;
+            Box::new(mesh)
+        }
+        _ => return Err(format!("unknown directive '{}'", kind)),
+    };
+    match group {
+        Some(g) => g.objects.push(obj),
+        None => scene.add_boxed(obj),
+    }
+    Ok(())
+}
+
+fn find_group(scene: &Scene, fields: &Fields, key: &str) -> Result<Arc<Group>, String> {
+    let name = fields.req(key, |s| Ok(s.to_string()))?;
+    match scene.groups.iter().find(|g| g.name == name) {
+        Some(g) => Ok(g.clone()),
+        None => Err(format!("{}: unknown group '{}'", fields.kind, name)),
+    }
+}
+
+// An environment or sky line; there can only be one of them.
+fn set_env(scene: &mut Scene, fields: &Fields, env: Box<dyn Environment>) -> Result<(), String> {
+    if scene.env.is_some() {
+        return Err(format!("{}: the environment is already given", fields.kind));
+    }
+    scene.env = Some(env);
+    Ok(())
+}
+
+// fields that describe a material
+const MATERIAL_FIELDS: [&str; 10] = [
+    "e", "c", "refl", "rough", "metal", "ior", "absorb", "scatter", "g", "tex",
+];
+
+// The material named by `mat`, or a new one from the fields on the line.
+fn material(scene: &mut Scene, fields: &Fields) -> Result<usize, String> {
+    let name = match fields.get("mat") {
+        Some(name) => name,
+        None => {
+            let m = new_material(scene, fields)?;
+            return Ok(scene.material_boxed(m));
+        }
+    };
+    if let Some(k) = MATERIAL_FIELDS.iter().find(|k| fields.get(k).is_some()) {
+        return Err(format!("{}: '{}' can't be used with 'mat'", fields.kind, k));
+    }
+    scene
+        .find_material(name)
+        .ok_or_else(|| format!("{}: unknown material '{}'", fields.kind, name))
+}
+
+fn new_material(scene: &Scene, fields: &Fields) -> Result<Box<dyn Material>, String> {
+    let refl = fields.opt("refl", "diff", parse_refl)?;
+    let only: [(&str, &[&str]); 6] = [
+        ("rough", &["metal", "frosted"]),
+        ("metal", &["metal"]),
+        ("ior", &["refr", "frosted"]),
+        ("absorb", &["refr", "frosted", "volume"]),
+        ("scatter", &["refr", "frosted", "volume"]),
+        ("g", &["refr", "frosted", "volume"]),
+    ];
+    for (key, refls) in only {
+        if fields.get(key).is_some() && !refls.contains(&refl) {
+            return Err(format!(
+                "{}: '{}' needs refl={}",
+                fields.kind,
+                key,
+                refls.join(" or refl=")
+            ));
+        }
+    }
+    if refl == "volume" {
+        if let Some(k) = ["e", "c", "tex"].iter().find(|k| fields.get(k).is_some()) {
+            return Err(format!(
+                "{}: '{}' can't be used with refl=volume",
+                fields.kind, k
+            ));
+        }
+        return Ok(Box::new(Volume::new(medium(fields, Vec3::zero())?)));
+    }
+    let e = fields.opt("e", Vec3::zero(), parse_vec)?;
+    let metal = fields.opt("metal", None, parse_metal)?;
+    let tex = match fields.get("tex") {
+        Some(name) => match scene.textures.iter().find(|t| t.name == name) {
+            Some(t) => Some(t.clone()),
+            None => return Err(format!("{}: unknown texture '{}'", fields.kind, name)),
+        },
+        None => None,
+    };
+    // a named metal or a texture has its own colour
+    let c = if metal.is_some() || tex.is_some() {
+        Vec3::new(1.0, 1.0, 1.0)
+    } else {
+        Vec3::zero()
+    };
+    let c = fields.opt("c", c, parse_vec)?;
+    let rough = fields.opt("rough", 0.0, parse_rough)?;
+    let ior = fields.opt("ior", GLASS, parse_positive)?;
+    let absorb = fields.opt("absorb", Vec3::zero(), parse_absorb)?;
+    let mat: Box<dyn Material> = match refl {
+        "diff" => Box::new(Diffuse::new(e, c)),
+        "spec" => Box::new(Mirror::new(e, c)),
+        "refr" => Box::new(Glass::new(e, c, ior, absorb)),
+        "metal" => Box::new(RoughMetal::new(e, c, rough, metal)),
+        _ => Box::new(Frosted::new(e, c, rough, ior, absorb)),
+    };
+    let mat: Box<dyn Material> = match fields.get("scatter") {
+        Some(_) => {
+            let m = medium(fields, absorb)?;
+            Box::new(Filled::new(mat, m.scatter, m.g))
+        }
+        None => mat,
+    };
+    Ok(match tex {
+        Some(t) => Box::new(Textured::new(mat, t)),
+        None => mat,
+    })
+}
+
+// The medium of a fog line or volume, absorbing `absorb` unless the line
+// gives its own.
+fn medium(fields: &Fields, absorb: Vec3) -> Result<Medium, String> {
+    Ok(Medium::new(
+        fields.opt("absorb", absorb, parse_absorb)?,
+        fields.opt("scatter", Vec3::zero(), parse_scatter)?,
+        fields.opt("g", 0.0, parse_g)?,
+    ))
+}
+
+fn transform(fields: &Fields) -> Result<Transform, String> {
+    Ok(Transform::new(
+        fields.opt("scale", Vec3::new(1.0, 1.0, 1.0), parse_scale)?,
+        fields.opt("rotate", Vec3::zero(), parse_vec)?,
+        fields.opt("translate", Vec3::zero(), parse_vec)?,
+    ))
+}
+
+// Wraps obj in an Instance if the line moves it.
+fn place(fields: &Fields, obj: impl Shape + 'static) -> Result<Box<dyn Shape>, String> {
+    if ["scale", "rotate", "translate"]
+        .iter()
+        .all(|k| fields.get(k).is_none())
+    {
+        return Ok(Box::new(obj));
+    }
+    Ok(Box::new(Instance::new(Arc::new(obj), transform(fields)?)))
+}
+
+struct Fields<'a> {
+    kind: &'a str,
+    kv: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(kind: &'a str, words: impl Iterator<Item = &'a str>) -> Result<Fields<'a>, String> {
+        let mut kv: Vec<(&str, &str)> = Vec::new();
+        for w in words {
+            let (k, v) = w
+                .split_once('=')
+                .ok_or_else(|| format!("{}: expected key=value, got '{}'", kind, w))?;
+            if kv.iter().any(|&(k2, _)| k2 == k) {
+                return Err(format!("{}: field '{}' given twice", kind, k));
+            }
+            kv.push((k, v));
+        }
+        Ok(Fields { kind, kv })
+    }
+
+    fn check(&self, known: &[&str]) -> Result<(), String> {
+        match self.kv.iter().find(|(k, _)| !known.contains(k)) {
+            Some((k, _)) => Err(format!("{}: unknown field '{}'", self.kind, k)),
+            None => Ok(()),
+        }
+    }
+
+    // known plus the material and placement fields every shape takes
+    fn check_shape(&self, known: &[&str]) -> Result<(), String> {
+        let mut all = known.to_vec();
+        all.extend(MATERIAL_FIELDS);
+        all.extend(["mat", "scale", "rotate", "translate"]);
+        self.check(&all)
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.kv.iter().find(|(k, _)| *k == key).map(|&(_, v)| v)
+    }
+
+    fn req<T>(&self, key: &str, f: fn(&str) -> Result<T, String>) -> Result<T, String> {
+        match self.get(key) {
+            Some(v) => f(v).map_err(|e| format!("{}: field '{}': {}", self.kind, key, e)),
+            None => Err(format!("{}: missing field '{}'", self.kind, key)),
+        }
+    }
+
+    fn opt<T>(&self, key: &str, def: T, f: fn(&str) -> Result<T, String>) -> Result<T, String> {
+        match self.get(key) {
+            Some(_) => self.req(key, f),
+            None => Ok(def),
+        }
+    }
+}
+
+// no infinities or NaN, which would only turn up later as NaN areas and
+// inverted normals
+fn parse_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(x),
+        _ => Err(format!("invalid number '{}'", s)),
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f64, String> {
+    match parse_f64(s)? {
+        x if x > 0.0 => Ok(x),
+        x => Err(format!("{} is not positive", x)),
+    }
+}
+
+fn parse_usize(s: &str) -> Result<usize, String> {
+    s.parse().map_err(|_| format!("invalid integer '{}'", s))
+}
+
+// sizes and counts that 0 would make meaningless
+fn parse_count(s: &str) -> Result<usize, String> {
+    match parse_usize(s)? {
+        0 => Err("0 is not positive".to_string()),
+        n => Ok(n),
+    }
+}
+
+fn parse_vec(s: &str) -> Result<Vec3, String> {
+    let v = s
+        .split(',')
+        .map(parse_f64)
+        .collect::<Result<Vec<f64>, String>>()?;
+    if v.len() != 3 {
+        return Err(format!("expected 3 comma-separated numbers, got '{}'", s));
+    }
+    Ok(Vec3::new(v[0], v[1], v[2]))
+}
+
+// one number for uniform scaling, or x,y,z
+fn parse_scale(s: &str) -> Result<Vec3, String> {
+    let v = if s.contains(',') {
+        parse_vec(s)?
+    } else {
+        let k = parse_f64(s)?;
+        Vec3::new(k, k, k)
+    };
+    if v.x == 0.0 || v.y == 0.0 || v.z == 0.0 {
+        return Err("scale must not be zero".to_string());
+    }
+    Ok(v)
+}
+
+fn parse_projection(s: &str) -> Result<Projection, String> {
+    Projection::from_name(s).ok_or_else(|| {
+        format!(
+            "expected perspective, ortho, fisheye or equirect, got '{}'",
+            s
+        )
+    })
+}
+
+fn parse_op(s: &str) -> Result<Op, String> {
+    Op::from_name(s)
+        .ok_or_else(|| format!("expected union, intersection or difference, got '{}'", s))
+}
+
+fn parse_refl(s: &str) -> Result<&'static str, String> {
+    match s {
+        "diff" => Ok("diff"),
+        "spec" => Ok("spec"),
+        "refr" => Ok("refr"),
+        "metal" => Ok("metal"),
+        "frosted" => Ok("frosted"),
+        "volume" => Ok("volume"),
+        _ => Err(format!(
+            "expected diff, spec, refr, metal, frosted or volume, got '{}'",
+            s
+        )),
+    }
+}
+
+fn parse_texture(s: &str) -> Result<&'static str, String> {
+    match s {
+        "image" => Ok("image"),
+        "checker" => Ok("checker"),
+        "noise" => Ok("noise"),
+        "gradient" => Ok("gradient"),
+        _ => Err(format!(
+            "expected image, checker, noise or gradient, got '{}'",
+            s
+        )),
+    }
+}
+
+fn parse_metal(s: &str) -> Result<Option<Metal>, String> {
+    match Metal::from_name(s) {
+        Some(m) => Ok(Some(m)),
+        None => Err(format!(
+            "expected gold, copper, aluminium or silver, got '{}'",
+            s
+        )),
+    }
+}
+
+fn parse_absorb(s: &str) -> Result<Vec3, String> {
+    let v = parse_vec(s)?;
+    if v.x < 0.0 || v.y < 0.0 || v.z < 0.0 {
+        return Err("absorption must not be negative".to_string());
+    }
+    Ok(v)
+}
+
+fn parse_scatter(s: &str) -> Result<Vec3, String> {
+    let v = parse_vec(s)?;
+    if v.x < 0.0 || v.y < 0.0 || v.z < 0.0 {
+        return Err("scattering must not be negative".to_string());
+    }
+    Ok(v)
+}
+
+// Henyey-Greenstein asymmetry; at -1 or 1 all light would go one way
+fn parse_g(s: &str) -> Result<f64, String> {
+    match parse_f64(s)? {
+        x if x > -1.0 && x < 1.0 => Ok(x),
+        x => Err(format!("{} is not between -1 and 1", x)),
+    }
+}
+
+fn parse_rough(s: &str) -> Result<f64, String> {
+    match parse_f64(s)? {
+        x if (0.0..=1.0).contains(&x) => Ok(x),
+        x => Err(format!("{} is not between 0 and 1", x)),
+    }
+}
+
+fn parse_intensity(s: &str) -> Result<f64, String> {
+    match parse_f64(s)? {
+        x if x >= 0.0 => Ok(x),
+        x => Err(format!("{} is negative", x)),
+    }
+}
+
+// of the sun, in degrees
+fn parse_elevation(s: &str) -> Result<f64, String> {
+    match parse_f64(s)? {
+        x if (0.0..=90.0).contains(&x) => Ok(x),
+        x => Err(format!("{} is not between 0 and 90", x)),
+    }
+}
+
+// the range the Preetham sky was fitted over
+fn parse_turbidity(s: &str) -> Result<f64, String> {
+    match parse_f64(s)? {
+        x if (2.0..=10.0).contains(&x) => Ok(x),
+        x => Err(format!("{} is not between 2 and 10", x)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenes::SCENES;
+
+    // files are found again from wherever the scene is saved
+    #[test]
+    fn file_paths() {
+        let image = "texture name=sky type=image file=maps/sky.hdr\n";
+        for scene in [
+            load("scenes/mesh.scene").unwrap(),
+            load("scenes/envmap.scene").unwrap(),
+            parse(image, Path::new("scenes")).unwrap(),
+        ] {
+            let saved = to_string(&scene);
+            let loaded = parse(&saved, Path::new("/nonexistent")).unwrap();
+            assert_eq!(saved, to_string(&loaded));
+        }
+    }
+
+    // save followed by load gives back the same scene
+    #[test]
+    fn round_trip() {
+        for def in &SCENES {
+            let saved = to_string(&(def.build)());
+            let loaded = parse(&saved, Path::new(""))
+                .unwrap_or_else(|e| panic!("{}: {}\n{}", def.name, e, saved));
+            assert_eq!(saved, to_string(&loaded), "{}", def.name);
+        }
+    }
+
+    // the message of the error parsing src gives
+    fn error(src: &str) -> String {
+        match parse(src, Path::new("")) {
+            Ok(_) => panic!("parsed without error: {}", src),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn unknown_directive() {
+        let e = error("settings w=4 h=4\nlight p=0,0,0\n");
+        assert_eq!(e, "line 2: unknown directive 'light'");
+    }
+
+    #[test]
+    fn bad_number() {
+        let e = error("# a comment\n\nsphere rad=1x p=0,0,0\n");
+        assert_eq!(e, "line 3: sphere: field 'rad': invalid number '1x'");
+        let e = error("sphere rad=1 p=0,0\n");
+        assert!(e.starts_with("line 1: sphere: field 'p': "), "{}", e);
+    }
+
+    #[test]
+    fn unknown_material() {
+        let e = error("material name=red c=1,0,0\nsphere rad=1 p=0,0,0 mat=blue\n");
+        assert_eq!(e, "line 2: sphere: unknown material 'blue'");
+        let e = error("material c=1,0,0\n");
+        assert_eq!(e, "line 1: material: missing field 'name'");
+    }
+
+    #[test]
+    fn sizes_not_positive() {
+        let lines = [
+            (
+                "sphere rad=0 p=0,0,0",
+                "sphere: field 'rad': 0 is not positive",
+            ),
+            (
+                "sphere rad=-2 p=0,0,0",
+                "sphere: field 'rad': -2 is not positive",
+            ),
+            (
+                "sphere rad=NaN p=0,0,0",
+                "sphere: field 'rad': invalid number 'NaN'",
+            ),
+            (
+                "disk p=0,0,0 n=0,1,0 rad=inf",
+                "disk: field 'rad': invalid number 'inf'",
+            ),
+            (
+                "cylinder p0=0,0,0 p1=0,1,0 rad=-1",
+                "cylinder: field 'rad': -1 is not positive",
+            ),
+            (
+                "cone p0=0,0,0 p1=0,1,0 rad=0",
+                "cone: field 'rad': 0 is not positive",
+            ),
+            (
+                "torus p=0,0,0 n=0,1,0 major=2 minor=-1",
+                "torus: field 'minor': -1 is not positive",
+            ),
+            (
+                "box min=0,0,0 max=1,inf,1",
+                "box: field 'max': invalid number 'inf'",
+            ),
+            (
+                "sphere rad=1 p=0,0,0 scale=inf",
+                "sphere: field 'scale': invalid number 'inf'",
+            ),
+        ];
+        for (line, msg) in lines {
+            assert_eq!(error(&format!("\n{}\n", line)), format!("line 2: {}", msg));
+        }
+    }
+
+    #[test]
+    fn settings_not_positive() {
+        for f in ["w", "h", "spp", "max_depth"] {
+            let e = error(&format!("settings {}=0\n", f));
+            assert_eq!(
+                e,
+                format!("line 1: settings: field '{}': 0 is not positive", f)
+            );
+            let e = error(&format!("\nsettings {}=-1\n", f));
+            assert_eq!(
+                e,
+                format!("line 2: settings: field '{}': invalid integer '-1'", f)
+            );
+        }
+        // no roulette at all is allowed
+        assert!(parse("settings rr_depth=0\n", Path::new("")).is_ok());
+    }
+}
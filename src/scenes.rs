@@ -0,0 +1,434 @@
+// Scene registry: the scenes from smallpt-model-data.rs, buildable at runtime.
+
+use crate::camera::Camera;
+use crate::csg::Op;
+use crate::instance::Group;
+use crate::material::{from_refl, Diffuse, Glass, Mirror, Refl, Textured, DIAMOND, GLASS, WATER};
+use crate::medium::{Medium, Volume};
+use crate::microfacet::{Frosted, Metal, RoughMetal};
+use crate::prim::{Cone, Cuboid, Cylinder, Disk, Torus};
+use crate::scene::Scene;
+use crate::shape::{Shape, Sphere};
+use crate::texture::{Kind, Texture};
+use crate::transform::Transform;
+use crate::Vec3;
+use std::f64::consts::{FRAC_PI_6, SQRT_2};
+use std::sync::Arc;
+
+// unit scale, for transforms that only move things
+const ONE: Vec3 = Vec3::new(1.0, 1.0, 1.0);
+
+// Inside the Cornell box, so primary rays never start above the ceiling.
+fn cornell_cam() -> Camera {
+    Camera::look_at(
+        Vec3::new(50.0, 48.0, 169.0),
+        Vec3::new(50.0, 42.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        64.0,
+    )
+}
+
+// A sphere on its own as a group, to be used in csg.
+fn ball(scene: &mut Scene, name: &str, rad: f64, p: Vec3, c: Vec3, refl: Refl) -> Arc<Group> {
+    let m = scene.material_boxed(from_refl(Vec3::zero(), c, refl));
+    scene.group(name, vec![Box::new(Sphere::new(rad, p, m))])
+}
+
+pub struct SceneDef {
+    pub name: &'static str,
+    pub build: fn() -> Scene,
+}
+
+#[rustfmt::skip]
+pub const SCENES: [SceneDef; 15] = [
+    SceneDef { name: "debug", build: debug },
+    SceneDef { name: "cornell", build: cornell },
+    SceneDef { name: "planes", build: planes },
+    SceneDef { name: "csg", build: csg },
+    SceneDef { name: "props", build: props },
+    SceneDef { name: "metals", build: metals },
+    SceneDef { name: "glass", build: glass },
+    SceneDef { name: "sky", build: sky },
+    SceneDef { name: "nightsky", build: nightsky },
+    SceneDef { name: "island", build: island },
+    SceneDef { name: "vista", build: vista },
+    SceneDef { name: "overlap", build: overlap },
+    SceneDef { name: "wada", build: wada },
+    SceneDef { name: "wada2", build: wada2 },
+    SceneDef { name: "forest", build: forest },
+];
+
+pub fn find(name: &str) -> Option<&'static SceneDef> {
+    SCENES.iter().find(|s| s.name == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    SCENES.iter().map(|s| s.name).collect()
+}
+
+//-------------Debug Scene sc1-------------
+#[rustfmt::skip]
+fn debug() -> Scene {
+    let mut scene = Scene::new(cornell_cam());
+    scene.sphere(1e5,   Vec3::new( 1e5 + 1.0,      40.8, 81.6), Vec3::zero(),               Vec3::new(0.75, 0.25, 0.25), Refl::Diff);//left
+    scene.sphere(1e5,   Vec3::new(-1e5 + 99.0,     40.8, 81.6), Vec3::zero(),               Vec3::new(0.25, 0.25, 0.75), Refl::Diff);//right
+    scene.sphere(1e5,   Vec3::new(50.0,            40.8, 1e5),  Vec3::zero(),               Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//front
+    scene.sphere(1e5,   Vec3::new(50.0,    40.8,-1e5 + 170.0),  Vec3::zero(),               Vec3::zero(), Refl::Diff);//back
+    scene.sphere(1e5,   Vec3::new(50.0,            1e5, 81.6),  Vec3::zero(),               Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//bottom
+    scene.sphere(1e5,   Vec3::new(50.0,-1e5 + 81.6+4.0, 81.6),  Vec3::zero(),               Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//top
+    scene.sphere(16.5,  Vec3::new(27.0,           16.5, 47.0),  Vec3::zero(),               Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec);
+    scene.sphere(16.5,  Vec3::new(73.0,           16.5, 78.0),  Vec3::zero(),               Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Refr);
+    scene.sphere(600.0, Vec3::new(50.0, 681.6-0.27+4.0, 81.6),  Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff);
+    scene
+}
+
+//----------cornel box sc1-----------
+#[rustfmt::skip]
+fn cornell() -> Scene {
+    let mut scene = Scene::new(cornell_cam());
+    scene.sphere(1e5,  Vec3::new(1e5 + 1.0,       40.8, 81.6), Vec3::zero(),                 Vec3::new(0.75, 0.25, 0.25), Refl::Diff);
+    scene.sphere(1e5,  Vec3::new(-1e5 + 99.0,     40.8, 81.6), Vec3::zero(),                 Vec3::new(0.25, 0.25, 0.75), Refl::Diff);
+    scene.sphere(1e5,  Vec3::new(50.0,            40.8, 1e5 ), Vec3::zero(),                 Vec3::new(0.75, 0.75, 0.75), Refl::Diff);
+    scene.sphere(1e5,  Vec3::new(50.0,     40.8,-1e5 + 170.0), Vec3::zero(),                 Vec3::zero(), Refl::Diff);
+    scene.sphere(1e5,  Vec3::new(50.0,             1e5, 81.6), Vec3::zero(),                 Vec3::new(0.75, 0.75, 0.75), Refl::Diff);
+    scene.sphere(1e5,  Vec3::new(50.0, -1e5 + 81.6+4.0, 81.6), Vec3::zero(),                 Vec3::new(0.75, 0.75, 0.75), Refl::Diff);
+    scene.sphere(16.5, Vec3::new(27.0,            16.5, 47.0), Vec3::zero(),                 Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec);
+    scene.sphere(16.5, Vec3::new(73.0,            16.5, 78.0), Vec3::zero(),                 Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Refr);
+    scene.sphere(1.5,  Vec3::new(50.0,       81.6-16.5, 81.6), Vec3::new(4.0,4.0,4.0)*100.0, Vec3::zero(), Refl::Diff);
+    scene
+}
+
+//----------cornel box with plane walls and a quad light-----------
+#[rustfmt::skip]
+fn planes() -> Scene {
+    let mut scene = Scene::new(cornell_cam());
+    scene.plane(Vec3::new(1.0,  0.0,   0.0), Vec3::new( 1.0, 0.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff);//left
+    scene.plane(Vec3::new(99.0, 0.0,   0.0), Vec3::new(-1.0, 0.0,  0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff);//right
+    scene.plane(Vec3::new(0.0,  0.0,   0.0), Vec3::new( 0.0, 0.0,  1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//back
+    scene.plane(Vec3::new(0.0,  0.0, 170.0), Vec3::new( 0.0, 0.0, -1.0), Vec3::zero(), Vec3::zero(), Refl::Diff);//front
+    scene.plane(Vec3::new(0.0,  0.0,   0.0), Vec3::new( 0.0, 1.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//bottom
+    scene.plane(Vec3::new(0.0, 85.6,   0.0), Vec3::new( 0.0,-1.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//top
+    scene.quad(Vec3::new(35.0, 85.5, 60.0), Vec3::new(30.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 30.0), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff);//light
+    scene.quad(Vec3::new(60.0, 0.0, 20.0), Vec3::new(30.0, 0.0, 10.0), Vec3::new(0.0, 50.0, 0.0), Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec);//mirror
+    scene.sphere(16.5, Vec3::new(27.0, 16.5, 47.0), Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec);
+    scene.sphere(16.5, Vec3::new(60.0, 16.5, 78.0), Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Refr);
+    scene
+}
+
+//-----------sky sc2--------------
+#[rustfmt::skip]
+fn sky() -> Scene {
+    let mut scene = Scene::default();
+    let cen = Vec3::new(50.0, 40.8, -860.0);
+    scene.sphere(1600.0, Vec3::new(1.0,0.0,2.0)*3000.0, Vec3::new(1.0,0.9,0.8)*1.2e1*1.56*2.0,  Vec3::zero(), Refl::Diff); // sun
+    scene.sphere(1560.0, Vec3::new(1.0,0.0,2.0)*3500.0, Vec3::new(1.0,0.5,0.05)*4.8e1*1.56*2.0, Vec3::zero(), Refl::Diff); // horizon sun2
+    scene.sphere(10000.0,
+        cen+Vec3::new(0.0,0.0,-200.0),
+        Vec3::new(0.00063842, 0.02001478, 0.28923243)*6e-2*8.0,
+        Vec3::new(0.7,0.7,1.0)*0.25, Refl::Diff); // sky
+
+    scene.sphere(100000.0, Vec3::new(50.0,-100000.0,0.0),   Vec3::zero(),               Vec3::new(0.3,0.3,0.3), Refl::Diff); // grnd
+    scene.sphere(110000.0, Vec3::new(50.0,-110048.5,0.0),   Vec3::new(0.9,0.5,0.05)*4.0, Vec3::zero(),          Refl::Diff); // horizon brightener
+    scene.sphere(4e4,      Vec3::new(50.0,-4e4-30.0,-3000.0), Vec3::zero(),             Vec3::new(0.2,0.2,0.2), Refl::Diff); // mountains
+
+    scene.sphere(26.5, Vec3::new(22.0,26.5,42.0), Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.596,    Refl::Spec); // white Mirr
+    scene.sphere(13.0, Vec3::new(75.0,13.0,82.0), Vec3::zero(), Vec3::new(0.96,0.96,0.96)*0.96, Refl::Refr); // Glas
+    scene.sphere(22.0, Vec3::new(87.0,22.0,24.0), Vec3::zero(), Vec3::new(0.6,0.6,0.6)*0.696,   Refl::Refr); // Glas2
+    scene
+}
+
+//------------nightsky sc3----
+#[rustfmt::skip]
+fn nightsky() -> Scene {
+    let mut scene = Scene::default();
+    scene.sphere(2.5e3, Vec3::new(0.82,0.92,-2.0)*1e4,     Vec3::new(1.0,1.0,1.0)*0.8e2,        Vec3::zero(), Refl::Diff); // moon
+    scene.sphere(2.5e4, Vec3::new(50.0, 0.0, 0.0),         Vec3::new(0.114, 0.133, 0.212)*1e-2, Vec3::new(0.216,0.384,1.0)*0.003, Refl::Diff); // sky
+    scene.sphere(5e0,   Vec3::new(-0.2,0.16,-1.0)*1e4,     Vec3::new(1.00, 0.843, 0.698)*1e2,   Vec3::zero(), Refl::Diff); // star
+    scene.sphere(5e0,   Vec3::new(0.0,0.18,-1.0)*1e4,      Vec3::new(1.00, 0.851, 0.710)*1e2,   Vec3::zero(), Refl::Diff); // star
+    scene.sphere(5e0,   Vec3::new(0.3,0.15,-1.0)*1e4,      Vec3::new(0.671, 0.780, 1.00)*1e2,   Vec3::zero(), Refl::Diff); // star
+    let pool = scene.material(Glass::new(Vec3::zero(), Vec3::new(0.6,0.8,1.0)*0.01, WATER, Vec3::zero()));
+    scene.add(Sphere::new(3.5e4, Vec3::new(600.0,-3.5e4+1.0,300.0), pool)); //pool
+    let grass = scene.texture(Texture::new("grass", 4000.0, Kind::Noise(Vec3::new(1.0,1.0,1.0)*0.25, Vec3::new(1.0,1.0,1.0)*0.45)));
+    let hill = scene.material(Textured::new(Box::new(Diffuse::new(Vec3::zero(), ONE)), grass));
+    scene.add(Sphere::new(5e4, Vec3::new(-500.0,-5e4,0.0), hill)); //hill
+    let hut  = ball(&mut scene, "hut",  16.5, Vec3::new(27.0,0.0,47.0),                     Vec3::new(1.0,1.0,1.0)*0.33, Refl::Diff);
+    let door = ball(&mut scene, "door", 7.0,  Vec3::new(27.0+8.0*SQRT_2,0.0,47.0+8.0*SQRT_2), Vec3::new(1.0,1.0,1.0)*0.33, Refl::Diff);
+    scene.csg(Op::Difference, hut, door); //hut with the door carved in
+    scene.sphere(500.0, Vec3::new(-1e3,-300.0,-3e3),       Vec3::zero(),                        Vec3::new(1.0,1.0,1.0)*0.351, Refl::Diff); //mnt
+    scene.sphere(830.0, Vec3::new(0.0,-500.0,-3e3),        Vec3::zero(),                        Vec3::new(1.0,1.0,1.0)*0.354, Refl::Diff); //mnt
+    scene.sphere(490.0, Vec3::new(1e3,-300.0,-3e3),        Vec3::zero(),                        Vec3::new(1.0,1.0,1.0)*0.352, Refl::Diff); //mnt
+    scene
+}
+
+//-----------island sc4-------
+#[rustfmt::skip]
+fn island() -> Scene {
+    let mut scene = Scene::default();
+    let cen = Vec3::new(50.0, -20.0, -860.0);
+    scene.sphere(160.0,   cen+Vec3::new(0.0, 600.0, -500.0), Vec3::new(1.0,1.0,1.0)*2e2,          Vec3::zero(), Refl::Diff); // sun
+    scene.sphere(800.0,   cen+Vec3::new(0.0,-880.0,-9120.0), Vec3::new(1.0,1.0,1.0)*2e1,          Vec3::zero(), Refl::Diff); // horizon
+    scene.sphere(10000.0, cen+Vec3::new(0.0,0.0,-200.00),    Vec3::new(0.0627, 0.188, 0.569)*1e0, Vec3::new(1.0,1.0,1.0)*0.4, Refl::Diff); // sky
+    let water = scene.material(Glass::new(Vec3::zero(), Vec3::new(0.110, 0.898, 1.00)*0.996, WATER, Vec3::zero()));
+    scene.add(Sphere::new(800.0, cen+Vec3::new(0.0,-720.0,-200.0), water)); // water
+    scene.sphere(790.0,   cen+Vec3::new(0.0,-720.0,-200.0),  Vec3::zero(),                        Vec3::new(0.4,0.3,0.04)*0.6,   Refl::Diff); // earth
+    let sand = scene.texture(Texture::new("sand", 64.0, Kind::Noise(Vec3::new(0.4,0.3,0.04)*0.6, Vec3::new(0.4,0.3,0.04)*0.9)));
+    let sand = scene.material(Textured::new(Box::new(Diffuse::new(Vec3::zero(), ONE)), sand));
+    scene.add(Sphere::new(325.0, cen+Vec3::new(0.0,-255.0,-50.0), sand)); // island
+    let grass = scene.texture(Texture::new("grass", 48.0, Kind::Noise(Vec3::new(0.02,0.3,0.02)*0.5, Vec3::new(0.02,0.3,0.02))));
+    let grass = scene.material(Textured::new(Box::new(Diffuse::new(Vec3::zero(), ONE)), grass));
+    scene.add(Sphere::new(275.0, cen+Vec3::new(0.0,-205.0,-33.0), grass)); // grass
+    scene
+}
+
+//-------------Vista sc5------------
+#[rustfmt::skip]
+fn vista() -> Scene {
+    let mut scene = Scene::default();
+    let cen = Vec3::new(50.0, -20.0, -860.0);
+    scene.sphere(8000.0, cen+Vec3::new(0.0,-8000.0,-900.0),  Vec3::new(1.0,0.4,0.1)*5e-1,        Vec3::zero(), Refl::Diff); // sun
+    scene.sphere(1e4,    cen+Vec3::zero(),                   Vec3::new(0.631, 0.753, 1.00)*3e-1, Vec3::new(1.0,1.0,1.0)*0.5, Refl::Diff); // sky
+
+    scene.sphere(150.0,  cen+Vec3::new(-350.0, 0.0,-100.0),  Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.3, Refl::Diff); // mnt
+    // the peak is rock outside the snow sphere and snow inside it
+    let rock = ball(&mut scene, "rock", 200.0, cen+Vec3::new(-210.0, 0.0,-100.0), Vec3::new(1.0,1.0,1.0)*0.3, Refl::Diff);
+    let peak = ball(&mut scene, "peak", 200.0, cen+Vec3::new(-210.0, 0.0,-100.0), Vec3::new(1.0,1.0,1.0)*0.8, Refl::Diff);
+    let snow = ball(&mut scene, "snow", 145.0, cen+Vec3::new(-210.0,85.0,-100.0), Vec3::new(1.0,1.0,1.0)*0.8, Refl::Diff);
+    scene.csg(Op::Difference, rock, snow.clone()); // mnt
+    scene.csg(Op::Intersection, peak, snow); // snow
+    scene.sphere(150.0,  cen+Vec3::new(-50.0,  0.0,-100.0),  Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.3, Refl::Diff); // mnt
+    scene.sphere(150.0,  cen+Vec3::new(100.0,  0.0,-100.0),  Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.3, Refl::Diff); // mnt
+    scene.sphere(125.0,  cen+Vec3::new(250.0,  0.0,-100.0),  Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.3, Refl::Diff); // mnt
+    scene.sphere(150.0,  cen+Vec3::new(375.0,  0.0,-100.0),  Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.3, Refl::Diff); // mnt
+
+    scene.sphere(2500.0, cen+Vec3::new(0.0,-2400.0,-500.0),  Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.1, Refl::Diff); // mnt base
+
+    let water = scene.material(Glass::new(Vec3::zero(), Vec3::new(0.2,0.2,1.0), WATER, Vec3::zero()));
+    scene.add(Sphere::new(8000.0, cen+Vec3::new(0.0,-8000.0, 200.0), water)); // water
+    scene.sphere(8000.0, cen+Vec3::new(0.0,-8000.0,1100.0),  Vec3::zero(), Vec3::new(0.0,0.3,0.0),       Refl::Diff); // grass
+    scene.sphere(8.0,    cen+Vec3::new(-75.0, -5.0, 850.0),  Vec3::zero(), Vec3::new(0.0,0.3,0.0),       Refl::Diff); // bush
+    scene.sphere(30.0,   cen+Vec3::new(0.0,   23.0, 825.0),  Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.996, Refl::Refr); // ball
+
+    // clouds are mist with no surface, and the air is hazy into the distance
+    scene.fog = Some(Medium::new(Vec3::zero(), Vec3::new(1.0,1.0,1.0)*2e-5, 0.6));
+    let cloud = scene.material(Volume::new(Medium::new(Vec3::new(1.0,1.0,1.0)*0.002, Vec3::new(1.0,1.0,1.0)*0.15, 0.8)));
+    scene.add(Sphere::new(30.0, cen+Vec3::new(200.0,280.0,-400.0), cloud)); // clouds
+    scene.add(Sphere::new(37.0, cen+Vec3::new(237.0,280.0,-400.0), cloud)); // clouds
+    scene.add(Sphere::new(28.0, cen+Vec3::new(267.0,280.0,-400.0), cloud)); // clouds
+
+    scene.add(Sphere::new(40.0, cen+Vec3::new(150.0,280.0,-1000.0), cloud)); // clouds
+    scene.add(Sphere::new(37.0, cen+Vec3::new(187.0,280.0,-1000.0), cloud)); // clouds
+
+    scene.add(Sphere::new(40.0, cen+Vec3::new(600.0,280.0,-1100.0), cloud)); // clouds
+    scene.add(Sphere::new(37.0, cen+Vec3::new(637.0,280.0,-1100.0), cloud)); // clouds
+
+    scene.add(Sphere::new(37.0, cen+Vec3::new(-800.0,280.0,-1400.0), cloud)); // clouds
+    scene.add(Sphere::new(37.0, cen+Vec3::new(0.0,   280.0,-1600.0), cloud)); // clouds
+    scene.add(Sphere::new(37.0, cen+Vec3::new(537.0, 280.0,-1800.0), cloud)); // clouds
+    scene
+}
+
+//----------------csg: lens, overlapping glass and a carved bowl-----------------
+#[rustfmt::skip]
+fn csg() -> Scene {
+    let mut scene = Scene::new(cornell_cam());
+    scene.plane(Vec3::new(1.0,  0.0,   0.0), Vec3::new( 1.0, 0.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff);//left
+    scene.plane(Vec3::new(99.0, 0.0,   0.0), Vec3::new(-1.0, 0.0,  0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff);//right
+    scene.plane(Vec3::new(0.0,  0.0,   0.0), Vec3::new( 0.0, 0.0,  1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//back
+    scene.plane(Vec3::new(0.0,  0.0, 170.0), Vec3::new( 0.0, 0.0, -1.0), Vec3::zero(), Vec3::zero(), Refl::Diff);//front
+    scene.plane(Vec3::new(0.0,  0.0,   0.0), Vec3::new( 0.0, 1.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//bottom
+    scene.plane(Vec3::new(0.0, 85.6,   0.0), Vec3::new( 0.0,-1.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//top
+    scene.quad(Vec3::new(35.0, 85.5, 60.0), Vec3::new(30.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 30.0), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff);//light
+
+    let glass = Vec3::new(1.0, 1.0, 1.0) * 0.999;
+    // biconvex lens facing the camera
+    let front = ball(&mut scene, "lens_front", 30.0, Vec3::new(25.0, 24.0, 40.0), glass, Refl::Refr);
+    let back  = ball(&mut scene, "lens_back",  30.0, Vec3::new(25.0, 24.0, 90.0), glass, Refl::Refr);
+    scene.csg(Op::Intersection, front, back);
+    // two glass balls melted into one, with no surface between them
+    let a = ball(&mut scene, "drop_a", 12.0, Vec3::new(62.0, 12.0, 70.0), glass, Refl::Refr);
+    let b = ball(&mut scene, "drop_b", 9.0,  Vec3::new(74.0, 15.0, 78.0), glass, Refl::Refr);
+    scene.csg(Op::Union, a, b);
+    // white ball hollowed out from the top
+    let outer = ball(&mut scene, "bowl",   12.0, Vec3::new(45.0, 12.0, 105.0), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);
+    let inner = ball(&mut scene, "hollow", 10.5, Vec3::new(45.0, 18.0, 105.0), Vec3::zero(), Refl::Diff);
+    scene.csg(Op::Difference, outer, inner);
+    scene
+}
+
+//----------------props: furniture from the analytic shapes-----------------
+#[rustfmt::skip]
+fn props() -> Scene {
+    let mut scene = Scene::new(cornell_cam());
+    scene.plane(Vec3::new(1.0,  0.0,   0.0), Vec3::new( 1.0, 0.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff);//left
+    scene.plane(Vec3::new(99.0, 0.0,   0.0), Vec3::new(-1.0, 0.0,  0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff);//right
+    scene.plane(Vec3::new(0.0,  0.0,   0.0), Vec3::new( 0.0, 0.0,  1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//back
+    scene.plane(Vec3::new(0.0,  0.0, 170.0), Vec3::new( 0.0, 0.0, -1.0), Vec3::zero(), Vec3::zero(), Refl::Diff);//front
+    scene.plane(Vec3::new(0.0,  0.0,   0.0), Vec3::new( 0.0, 1.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//bottom
+    scene.plane(Vec3::new(0.0, 85.6,   0.0), Vec3::new( 0.0,-1.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//top
+    let light = scene.material(Diffuse::new(Vec3::new(12.0, 12.0, 12.0), Vec3::zero()));
+    scene.add(Disk::new(Vec3::new(50.0, 85.5, 80.0), Vec3::new(0.0, -1.0, 0.0), 17.0, light));
+
+    // table
+    let wood = scene.material(Diffuse::new(Vec3::zero(), Vec3::new(0.6, 0.4, 0.2)));
+    scene.add(Cuboid::aligned(Vec3::new(20.0, 24.0, 40.0), Vec3::new(64.0, 27.0, 75.0), wood));
+    for (x, z) in [(23.0, 43.0), (61.0, 43.0), (23.0, 72.0), (61.0, 72.0)] {
+        scene.add(Cylinder::new(Vec3::new(x, 0.0, z), Vec3::new(x, 24.0, z), 1.5, wood));
+    }
+    // on the table
+    let gold = scene.material(Mirror::new(Vec3::zero(), Vec3::new(0.9, 0.7, 0.3)));
+    scene.add(Torus::new(Vec3::new(30.0, 29.0, 62.0), Vec3::new(0.0, 1.0, 0.0), 5.0, 2.0, gold));
+    let red = scene.material(Diffuse::new(Vec3::zero(), Vec3::new(0.75, 0.2, 0.2)));
+    scene.add(Cone::new(Vec3::new(52.0, 27.0, 55.0), Vec3::new(52.0, 45.0, 55.0), 7.0, red));
+    // a crate turned on the floor and a glass column
+    let blue = scene.material(Diffuse::new(Vec3::zero(), Vec3::new(0.3, 0.4, 0.7)));
+    scene.add(Cuboid::new(Vec3::new(66.0, 0.0, 95.0), Vec3::new(14.0, 0.0, -7.0), Vec3::new(0.0, 12.0, 0.0), Vec3::new(7.0, 0.0, 14.0), blue));
+    let glass = scene.material(Glass::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, GLASS, Vec3::zero()));
+    scene.add(Cylinder::new(Vec3::new(22.0, 0.0, 110.0), Vec3::new(22.0, 20.0, 110.0), 7.0, glass));
+    scene
+}
+
+//----------debug scene with rough metal and frosted glass spheres-----------
+#[rustfmt::skip]
+fn metals() -> Scene {
+    let mut scene = Scene::new(cornell_cam());
+    scene.sphere(1e5,   Vec3::new( 1e5 + 1.0,      40.8, 81.6), Vec3::zero(),               Vec3::new(0.75, 0.25, 0.25), Refl::Diff);//left
+    scene.sphere(1e5,   Vec3::new(-1e5 + 99.0,     40.8, 81.6), Vec3::zero(),               Vec3::new(0.25, 0.25, 0.75), Refl::Diff);//right
+    scene.sphere(1e5,   Vec3::new(50.0,            40.8, 1e5),  Vec3::zero(),               Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//front
+    scene.sphere(1e5,   Vec3::new(50.0,    40.8,-1e5 + 170.0),  Vec3::zero(),               Vec3::zero(), Refl::Diff);//back
+    scene.sphere(1e5,   Vec3::new(50.0,            1e5, 81.6),  Vec3::zero(),               Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//bottom
+    scene.sphere(1e5,   Vec3::new(50.0,-1e5 + 81.6+4.0, 81.6),  Vec3::zero(),               Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//top
+    scene.sphere(600.0, Vec3::new(50.0, 681.6-0.27+4.0, 81.6),  Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff);
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let gold      = scene.material(RoughMetal::new(Vec3::zero(), white, 0.2, Some(Metal::Gold)));
+    let frosted   = scene.material(Frosted::new(Vec3::zero(), white * 0.999, 0.15, GLASS, Vec3::zero()));
+    let copper    = scene.material(RoughMetal::new(Vec3::zero(), white, 0.35, Some(Metal::Copper)));
+    let aluminium = scene.material(RoughMetal::new(Vec3::zero(), white, 0.5, Some(Metal::Aluminium)));
+    let silver    = scene.material(RoughMetal::new(Vec3::zero(), white, 0.05, Some(Metal::Silver)));
+    scene.add(Sphere::new(16.5, Vec3::new(27.0, 16.5,  47.0), gold));
+    scene.add(Sphere::new(16.5, Vec3::new(73.0, 16.5,  78.0), frosted));
+    scene.add(Sphere::new(8.0,  Vec3::new(50.0,  8.0,  95.0), copper));
+    scene.add(Sphere::new(8.0,  Vec3::new(22.0,  8.0, 100.0), aluminium));
+    scene.add(Sphere::new(6.0,  Vec3::new(74.0,  6.0, 108.0), silver));
+    scene
+}
+
+//----------water, diamond and tinted glass in the plane walled box-----------
+#[rustfmt::skip]
+fn glass() -> Scene {
+    let mut scene = Scene::new(cornell_cam());
+    scene.plane(Vec3::new(1.0,  0.0,   0.0), Vec3::new( 1.0, 0.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff);//left
+    scene.plane(Vec3::new(99.0, 0.0,   0.0), Vec3::new(-1.0, 0.0,  0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff);//right
+    scene.plane(Vec3::new(0.0,  0.0,   0.0), Vec3::new( 0.0, 0.0,  1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//back
+    scene.plane(Vec3::new(0.0,  0.0, 170.0), Vec3::new( 0.0, 0.0, -1.0), Vec3::zero(), Vec3::zero(), Refl::Diff);//front
+    scene.plane(Vec3::new(0.0,  0.0,   0.0), Vec3::new( 0.0, 1.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//bottom
+    scene.plane(Vec3::new(0.0, 85.6,   0.0), Vec3::new( 0.0,-1.0,  0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);//top
+    scene.sphere(600.0, Vec3::new(50.0, 681.6-0.27+4.0, 81.6), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff);//light
+
+    // named, so the scene file lists them once
+    let white = Vec3::new(1.0, 1.0, 1.0) * 0.999;
+    let water   = scene.material(Glass::new(Vec3::zero(), white, WATER,   Vec3::new(0.04, 0.01, 0.002)));
+    let diamond = scene.material(Glass::new(Vec3::zero(), white, DIAMOND, Vec3::zero()));
+    let amber   = scene.material(Glass::new(Vec3::zero(), white, GLASS,   Vec3::new(0.002, 0.03, 0.06)));
+    scene.name_material(water, "water");
+    scene.name_material(diamond, "diamond");
+    scene.name_material(amber, "amber");
+    scene.add(Sphere::new(14.0, Vec3::new(25.0, 14.0, 60.0), water));
+    scene.add(Sphere::new(10.0, Vec3::new(50.0, 10.0, 95.0), diamond));
+    scene.add(Sphere::new(14.0, Vec3::new(75.0, 14.0, 60.0), amber));
+    scene.add(Sphere::new(5.0,  Vec3::new(64.0,  5.0, 100.0), amber));
+    scene
+}
+
+//----------------Overlap  sc6-----------------
+#[rustfmt::skip]
+fn overlap() -> Scene {
+    let mut scene = Scene::default();
+    scene.sphere(150.0, Vec3::new(50.0+75.0,28.0,62.0), Vec3::new(1.0,1.0,1.0)*0e-3, Vec3::new(1.0,0.9,0.8)*0.93, Refl::Refr);
+    scene.sphere(28.0,  Vec3::new(50.0+5.0,-28.0,62.0), Vec3::new(1.0,1.0,1.0)*1e1,  Vec3::zero(),                 Refl::Diff);
+    scene.sphere(300.0, Vec3::new(50.0,     28.0,62.0), Vec3::new(1.0,1.0,1.0)*0e-3, Vec3::new(1.0,1.0,1.0)*0.93, Refl::Spec);
+    scene
+}
+
+//----------------wada  sc7-------------
+#[rustfmt::skip]
+fn wada() -> Scene {
+    let mut scene = Scene::default();
+    let r = 60.0;
+    let d = r / FRAC_PI_6.cos();
+    let c = Vec3::new(50.0, 40.8, 62.0);
+    scene.sphere(1e5, Vec3::new(50.0, 100.0, 0.0),    Vec3::new(1.0,1.0,1.0)*3e0, Vec3::zero(),           Refl::Diff); // sky
+    scene.sphere(1e5, Vec3::new(50.0, -1e5-d-r, 0.0), Vec3::zero(),               Vec3::new(0.1,0.1,0.1), Refl::Diff); //grnd
+
+    // each ball sits on +x at distance d and is turned about c
+    let mut ball = |rot: Vec3, col: Vec3, refl: Refl| {
+        let m = scene.material_boxed(from_refl(Vec3::zero(), col, refl));
+        scene.instance(Arc::new(Sphere::new(r, Vec3::new(d, 0.0, 0.0), m)), Transform::new(ONE, rot, c));
+    };
+    ball(Vec3::new(0.0,  0.0,  30.0), Vec3::new(1.0,0.3,0.3)*0.999,    Refl::Spec); //red
+    ball(Vec3::new(0.0,  0.0, 150.0), Vec3::new(0.3,1.0,0.3)*0.999,    Refl::Spec); //grn
+    ball(Vec3::new(0.0,  0.0, -90.0), Vec3::new(0.3,0.3,1.0)*0.999,    Refl::Spec); //blue
+    ball(Vec3::new(0.0, 90.0,   0.0), Vec3::new(0.53,0.53,0.53)*0.999, Refl::Spec); //back
+    ball(Vec3::new(0.0,-90.0,   0.0), Vec3::new(1.0,1.0,1.0)*0.999,    Refl::Refr); //front
+    scene
+}
+
+//-----------------wada2 sc8----------
+#[rustfmt::skip]
+fn wada2() -> Scene {
+    let mut scene = Scene::default();
+    let r = 60.0;
+    let d = r / FRAC_PI_6.cos();
+    let e = Vec3::new(0.275, 0.612, 0.949);
+    let c = Vec3::new(50.0, 28.0, 62.0);
+    let k = r * 2.0 * f64::sqrt(2.0 / 3.0);
+    let mut ball = |dist: f64, rot: Vec3, e: Vec3| {
+        let m = scene.material(Mirror::new(e, Vec3::new(1.0,1.0,1.0)*0.996));
+        scene.instance(Arc::new(Sphere::new(r, Vec3::new(dist, 0.0, 0.0), m)), Transform::new(ONE, rot, c));
+    };
+    ball(d, Vec3::new(0.0,  0.0,  30.0), e*6e-2); //red
+    ball(d, Vec3::new(0.0,  0.0, 150.0), e*6e-2); //grn
+    ball(d, Vec3::new(0.0,  0.0, -90.0), e*6e-2); //blue
+    ball(k, Vec3::new(0.0, 90.0,   0.0), e*0e-2); //back
+    scene.sphere(2.0*2.0*k-k/3.0,
+        c+Vec3::new(0.0,0.0,-k/3.0), Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.5, Refl::Spec); //front
+    scene
+}
+
+//---------------forest sc9-----------
+#[rustfmt::skip]
+fn forest() -> Scene {
+    let mut scene = Scene::default();
+    let tc = Vec3::new(0.0588, 0.361, 0.0941);
+    let scc = Vec3::new(0.7, 0.7, 0.7);
+    scene.sphere(1e5, Vec3::new(50.0, 1e5+130.0, 0.0), Vec3::new(1.0,1.0,1.0)*1.3, Vec3::zero(),               Refl::Diff); //lite
+    scene.sphere(1e2, Vec3::new(50.0, -1e2+2.0, 47.0), Vec3::zero(),              Vec3::new(1.0,1.0,1.0)*0.7, Refl::Diff); //grnd
+    // mist between the trees, thicker in the reflections further off
+    scene.fog = Some(Medium::new(Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.002, 0.3));
+
+    // mirrors 1e4 away along z, turned about y
+    let mut mirror = |z: f64, ry: f64, o: Vec3| {
+        let m = scene.material(Mirror::new(Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.99));
+        scene.instance(Arc::new(Sphere::new(1e4, Vec3::new(0.0, 0.0, z), m)), Transform::new(ONE, Vec3::new(0.0, ry, 0.0), o));
+    };
+    mirror( 1e4, -50.0, Vec3::new(50.0, -30.0, 300.0)); // mirr L
+    mirror( 1e4,  50.0, Vec3::new(50.0, -30.0, 300.0)); // mirr R
+    mirror(-1e4,  30.0, Vec3::new(50.0, -30.0, -50.0)); // mirr FL
+    mirror(-1e4, -30.0, Vec3::new(50.0, -30.0, -50.0)); // mirr
+
+    // trunk, three layers of leaves and the snow on them, standing on the origin
+    let mut tree: Vec<Box<dyn Shape>> = Vec::new();
+    let mut part = |rad: f64, y: f64, c: Vec3| {
+        let m = scene.material(Diffuse::new(Vec3::zero(), c));
+        tree.push(Box::new(Sphere::new(rad, Vec3::new(0.0, y, 0.0), m)));
+    };
+    part(4.0,  6.0*0.6,                                   Vec3::new(0.13,0.066,0.033));
+    part(16.0, 6.0*2.0+16.0*0.6,                          tc);
+    part(11.0, 6.0*2.0+16.0*0.6*2.0+11.0*0.6,             tc);
+    part(7.0,  6.0*2.0+16.0*0.6*2.0+11.0*0.6*2.0+7.0*0.6, tc);
+    part(15.5, 1.8+6.0*2.0+16.0*0.6,                          scc);
+    part(10.5, 1.8+6.0*2.0+16.0*0.6*2.0+11.0*0.6,             scc);
+    part(6.5,  1.8+6.0*2.0+16.0*0.6*2.0+11.0*0.6*2.0+7.0*0.6, scc);
+    let tree = scene.group("tree", tree);
+    scene.instance(tree, Transform::new(ONE, Vec3::zero(), Vec3::new(50.0, 0.0, 47.0)));
+    scene
+}
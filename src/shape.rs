@@ -0,0 +1,387 @@
+use crate::bvh::Aabb;
+use crate::scenefile::vec_str;
+use crate::{random, Ray, Vec3, EPS};
+use std::f64::consts::PI;
+
+// What the integrator needs to know about the closest hit. The normal is the
+// outward surface normal (interpolated on smooth meshes), not flipped
+// towards the ray.
+pub struct Hit {
+    pub t: f64,
+    pub p: Vec3,
+    pub n: Vec3,
+    // surface parameterisation, for textures
+    pub uv: (f64, f64),
+    pub mat: usize,
+}
+
+// Stretch of a ray inside a solid. The hits carry the outward normals; t is
+// anywhere on the line, even behind the origin or infinite.
+pub struct Span {
+    pub enter: Hit,
+    pub exit: Hit,
+}
+
+// Uniformly distributed unit vector from two random numbers.
+pub fn sphere_dir(u: f64, v: f64) -> Vec3 {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let (s, c) = (2.0 * PI * v).sin_cos();
+    Vec3::new(r * c, r * s, z)
+}
+
+// Two unit vectors that make a right-handed frame with the unit vector n.
+pub fn basis(n: Vec3) -> (Vec3, Vec3) {
+    let a = if n.x.abs() > 0.1 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let u = (a % n).norm();
+    (u, n % u)
+}
+
+pub trait Shape: Send + Sync {
+    // nearest hit with EPS < t < t_max
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit>;
+    // Aabb::infinite() for unbounded shapes
+    fn bounds(&self) -> Aabb;
+    // index into Scene::materials; None for groups, whose members have their
+    // own
+    fn material(&self) -> Option<usize>;
+    // scene file line for the shape, without the material fields
+    fn export(&self) -> String;
+
+    // Shapes made of many primitives (meshes) hand them to the scene BVH
+    // one by one.
+    fn parts(&self) -> usize {
+        1
+    }
+    fn part_bounds(&self, _i: usize) -> Aabb {
+        self.bounds()
+    }
+    fn intersect_part(&self, _i: usize, ray: &Ray, t_max: f64) -> Option<Hit> {
+        self.intersect(ray, t_max)
+    }
+
+    // Closed shapes can take part in CSG: spans() lists where the whole line
+    // through the ray is inside, sorted and disjoint.
+    fn solid(&self) -> bool {
+        false
+    }
+    fn spans(&self, _ray: &Ray) -> Vec<Span> {
+        Vec::new()
+    }
+
+    // Uniform sampling of the surface, so that emitting shapes can be
+    // sampled as lights: a point and its normal, with density 1/area().
+    // Unbounded shapes, groups and csg have none.
+    fn area(&self) -> f64 {
+        0.0
+    }
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        None
+    }
+
+    // Direction from x towards the shape for direct lighting, with its
+    // density over solid angle and the distance to the surface along it;
+    // None for shapes that can't be sampled this way.
+    fn sample_from(&self, _x: Vec3) -> Option<(Vec3, f64, f64)> {
+        None
+    }
+    // density with which sample_from(x) picks d
+    fn pdf_from(&self, _x: Vec3, _d: Vec3) -> f64 {
+        0.0
+    }
+}
+
+pub struct Sphere {
+    pub rad: f64,
+    pub p: Vec3,
+    pub mat: usize,
+}
+
+impl Sphere {
+    pub fn new(rad: f64, p: Vec3, mat: usize) -> Sphere {
+        Sphere { rad, p, mat }
+    }
+
+    // both roots, nearest first
+    fn roots(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let po = self.p - ray.o;
+        let b = po.dot(&ray.d);
+        let d4 = b * b - po.dot(&po) + self.rad * self.rad;
+        if d4 < 0.0 {
+            return None;
+        }
+        let sqrt_d4 = d4.sqrt();
+        Some((b - sqrt_d4, b + sqrt_d4))
+    }
+
+    // axis and cosine of the half angle of the cone of directions from x
+    // that hit the sphere; from inside that is every direction
+    fn cone(&self, x: Vec3) -> (Vec3, f64) {
+        let w = self.p - x;
+        let d2 = w.dot(&w);
+        let r2 = self.rad * self.rad;
+        if d2 > r2 {
+            (w.norm(), (1.0 - r2 / d2).sqrt())
+        } else {
+            (Vec3::new(0.0, 0.0, 1.0), -1.0)
+        }
+    }
+
+    fn hit(&self, ray: &Ray, t: f64) -> Hit {
+        let p = ray.o + ray.d * t;
+        let n = (p - self.p).norm();
+        // longitude and latitude, v = 0 at the bottom
+        let uv = (
+            0.5 + n.z.atan2(n.x) / (2.0 * PI),
+            1.0 - n.y.clamp(-1.0, 1.0).acos() / PI,
+        );
+        Hit {
+            t,
+            p,
+            n,
+            uv,
+            mat: self.mat,
+        }
+    }
+}
+
+impl Shape for Sphere {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        let (t1, t2) = self.roots(ray)?;
+        let t = if t1 > EPS { t1 } else { t2 };
+        if t <= EPS || t >= t_max {
+            return None;
+        }
+        Some(self.hit(ray, t))
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = Vec3::new(self.rad, self.rad, self.rad);
+        Aabb::from_points(&[self.p - r, self.p + r])
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!("sphere rad={} p={}", self.rad, vec_str(self.p))
+    }
+
+    fn solid(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.rad * self.rad
+    }
+
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        let n = sphere_dir(random(), random());
+        Some((self.p + n * self.rad, n))
+    }
+
+    // uniform over the cone
+    fn sample_from(&self, x: Vec3) -> Option<(Vec3, f64, f64)> {
+        let (w, cos_max) = self.cone(x);
+        let (u, v) = basis(w);
+        let cos_a = 1.0 - random() * (1.0 - cos_max);
+        let sin_a = (1.0 - cos_a * cos_a).max(0.0).sqrt();
+        let (s, c) = (2.0 * PI * random()).sin_cos();
+        let d = (u * (c * sin_a) + v * (s * sin_a) + w * cos_a).norm();
+        // rounding can make the edge of the cone just miss
+        let t = match self.roots(&Ray::new(x, d)) {
+            Some((t0, t1)) => {
+                if t0 > 0.0 {
+                    t0
+                } else {
+                    t1
+                }
+            }
+            None => (self.p - x).dot(&d),
+        };
+        Some((d, 1.0 / (2.0 * PI * (1.0 - cos_max)), t))
+    }
+
+    fn pdf_from(&self, x: Vec3, d: Vec3) -> f64 {
+        let (w, cos_max) = self.cone(x);
+        if w.dot(&d) < cos_max {
+            0.0
+        } else {
+            1.0 / (2.0 * PI * (1.0 - cos_max))
+        }
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        match self.roots(ray) {
+            Some((t1, t2)) => vec![Span {
+                enter: self.hit(ray, t1),
+                exit: self.hit(ray, t2),
+            }],
+            None => Vec::new(),
+        }
+    }
+}
+
+// Infinite plane through p with normal n.
+pub struct Plane {
+    pub p: Vec3,
+    pub n: Vec3,
+    pub mat: usize,
+}
+
+impl Plane {
+    pub fn new(p: Vec3, n: Vec3, mat: usize) -> Plane {
+        Plane {
+            p,
+            n: n.norm(),
+            mat,
+        }
+    }
+
+    fn hit(&self, ray: &Ray, t: f64) -> Hit {
+        let p = ray.o + ray.d * t;
+        // plane coordinates in scene units
+        let (u, v) = basis(self.n);
+        let q = p - self.p;
+        Hit {
+            t,
+            p,
+            n: self.n,
+            uv: (q.dot(&u), q.dot(&v)),
+            mat: self.mat,
+        }
+    }
+}
+
+impl Shape for Plane {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        let dn = ray.d.dot(&self.n);
+        if dn.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.p - ray.o).dot(&self.n) / dn;
+        if t <= EPS || t >= t_max {
+            return None;
+        }
+        Some(self.hit(ray, t))
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::infinite()
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!("plane p={} n={}", vec_str(self.p), vec_str(self.n))
+    }
+
+    // as a solid, the half-space behind the plane
+    fn solid(&self) -> bool {
+        true
+    }
+
+    fn spans(&self, ray: &Ray) -> Vec<Span> {
+        let dn = ray.d.dot(&self.n);
+        let s = (ray.o - self.p).dot(&self.n);
+        let (t0, t1) = if dn.abs() < 1e-12 {
+            if s >= 0.0 {
+                return Vec::new();
+            }
+            (-f64::INFINITY, f64::INFINITY)
+        } else if dn > 0.0 {
+            (-f64::INFINITY, -s / dn)
+        } else {
+            (-s / dn, f64::INFINITY)
+        };
+        vec![Span {
+            enter: self.hit(ray, t0),
+            exit: self.hit(ray, t1),
+        }]
+    }
+}
+
+// Parallelogram with corner p and edges u, v; the normal is u x v.
+pub struct Quad {
+    pub p: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub mat: usize,
+    n: Vec3,
+    w: Vec3,
+}
+
+impl Quad {
+    pub fn new(p: Vec3, u: Vec3, v: Vec3, mat: usize) -> Quad {
+        let n = u % v;
+        Quad {
+            p,
+            u,
+            v,
+            mat,
+            n: n.norm(),
+            w: n * (1.0 / n.dot(&n)),
+        }
+    }
+}
+
+impl Shape for Quad {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<Hit> {
+        let dn = ray.d.dot(&self.n);
+        if dn.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.p - ray.o).dot(&self.n) / dn;
+        if t <= EPS || t >= t_max {
+            return None;
+        }
+        let p = ray.o + ray.d * t;
+        let q = p - self.p;
+        let a = self.w.dot(&(q % self.v));
+        let b = self.w.dot(&(self.u % q));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+        Some(Hit {
+            t,
+            p,
+            n: self.n,
+            uv: (a, b),
+            mat: self.mat,
+        })
+    }
+
+    fn bounds(&self) -> Aabb {
+        let (p, u, v) = (self.p, self.u, self.v);
+        Aabb::from_points(&[p, p + u, p + v, p + u + v])
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mat)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "quad p={} u={} v={}",
+            vec_str(self.p),
+            vec_str(self.u),
+            vec_str(self.v)
+        )
+    }
+
+    fn area(&self) -> f64 {
+        let c = self.u % self.v;
+        c.dot(&c).sqrt()
+    }
+
+    fn sample(&self) -> Option<(Vec3, Vec3)> {
+        Some((self.p + self.u * random() + self.v * random(), self.n))
+    }
+}
@@ -0,0 +1,246 @@
+// The Preetham et al. analytic daylight sky ("A Practical Analytic Model for
+// Daylight", 1999) and a sun disk to go with it, as an environment. Below
+// the horizon it is black; scenes bring their own ground.
+
+use crate::env::{equirect_dir, Environment, Equirect};
+use crate::light::luminance;
+use crate::shape::basis;
+use crate::texture::Image;
+use crate::{random, Vec3};
+use std::f64::consts::{FRAC_PI_2, PI};
+
+// The model gives luminance in kcd/m²; this brings full sun on a white
+// floor to about 1.
+const EXPOSURE: f64 = 1.0 / 40.0;
+
+// angular radius of the sun
+const SUN_RADIUS: f64 = 0.004_65;
+
+// luminance of the sun above the atmosphere, kcd/m²
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+// the sky is baked at this size to sample it by
+const BAKE_W: usize = 128;
+const BAKE_H: usize = 64;
+
+pub struct Sky {
+    // degrees above the horizon, and round from -z towards +x
+    pub elevation: f64,
+    pub azimuth: f64,
+    // haze, from 2 (very clear) to 10
+    pub turbidity: f64,
+    pub intensity: f64,
+    model: Preetham,
+    sun: Vec3,
+    // the sun through the atmosphere, exposure and intensity included
+    sun_radiance: Vec3,
+    // the sky without the sun, to sample by
+    map: Equirect,
+    // chance of sample() picking the sun rather than the sky
+    p_sun: f64,
+}
+
+impl Sky {
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, intensity: f64) -> Sky {
+        let (e, a) = (elevation.to_radians(), azimuth.to_radians());
+        let sun = Vec3::new(e.cos() * a.sin(), e.sin(), -e.cos() * a.cos());
+        let model = Preetham::new(sun, turbidity, EXPOSURE * intensity);
+        let px = (0..BAKE_W * BAKE_H)
+            .map(|k| {
+                let u = ((k % BAKE_W) as f64 + 0.5) / BAKE_W as f64;
+                let v = ((k / BAKE_W) as f64 + 0.5) / BAKE_H as f64;
+                model.radiance(equirect_dir(u, v))
+            })
+            .collect();
+        let img = Image {
+            w: BAKE_W,
+            h: BAKE_H,
+            px,
+        };
+        let map = Equirect::new(img, 0.0);
+        // nothing of a sun that has set
+        let sun_radiance = if e > -SUN_RADIUS {
+            let ts = FRAC_PI_2 - e;
+            transmittance(ts, turbidity) * (SUN_LUMINANCE * EXPOSURE * intensity)
+        } else {
+            Vec3::zero()
+        };
+        // both get picked now and then, so neither is left to bounces alone
+        let sun_total = luminance(sun_radiance) * 2.0 * PI * (1.0 - SUN_RADIUS.cos());
+        let p_sun = if sun_total > 0.0 {
+            (sun_total / (sun_total + map.total())).clamp(0.1, 0.9)
+        } else {
+            0.0
+        };
+        Sky {
+            elevation,
+            azimuth,
+            turbidity,
+            intensity,
+            model,
+            sun,
+            sun_radiance,
+            map,
+            p_sun,
+        }
+    }
+
+    // the part of the disk above the horizon, like the sky
+    fn in_sun(&self, d: Vec3) -> bool {
+        d.y > 0.0 && d.dot(&self.sun) >= SUN_RADIUS.cos()
+    }
+}
+
+impl Environment for Sky {
+    fn radiance(&self, d: Vec3) -> Vec3 {
+        let sky = self.model.radiance(d);
+        if self.in_sun(d) {
+            sky + self.sun_radiance
+        } else {
+            sky
+        }
+    }
+
+    // the sun, uniformly over its disk, or the sky by brightness
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        let d = if random() < self.p_sun {
+            let (u, v) = basis(self.sun);
+            let cos_max = SUN_RADIUS.cos();
+            let cos_a = 1.0 - random() * (1.0 - cos_max);
+            let sin_a = (1.0 - cos_a * cos_a).max(0.0).sqrt();
+            let (s, c) = (2.0 * PI * random()).sin_cos();
+            (u * (c * sin_a) + v * (s * sin_a) + self.sun * cos_a).norm()
+        } else {
+            self.map.sample()?.0
+        };
+        let pdf = self.pdf(d);
+        if pdf > 0.0 {
+            Some((d, pdf))
+        } else {
+            None
+        }
+    }
+
+    fn pdf(&self, d: Vec3) -> f64 {
+        let sun = if self.in_sun(d) {
+            1.0 / (2.0 * PI * (1.0 - SUN_RADIUS.cos()))
+        } else {
+            0.0
+        };
+        self.p_sun * sun + (1.0 - self.p_sun) * self.map.pdf(d)
+    }
+
+    fn export(&self) -> String {
+        format!(
+            "sky elevation={} azimuth={} turbidity={} intensity={}",
+            self.elevation, self.azimuth, self.turbidity, self.intensity
+        )
+    }
+}
+
+// The sky's luminance and chromaticity at the zenith, and the Perez et al.
+// coefficients giving them elsewhere relative to it, for each of Y, x and y.
+struct Preetham {
+    sun: Vec3,
+    // zenith angle of the sun
+    ts: f64,
+    zenith: [f64; 3],
+    coeffs: [[f64; 5]; 3],
+    scale: f64,
+}
+
+impl Preetham {
+    fn new(sun: Vec3, t: f64, scale: f64) -> Preetham {
+        let ts = sun.y.clamp(-1.0, 1.0).acos();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * ts);
+        let lum = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let poly = |c: [f64; 4]| ((c[0] * ts + c[1]) * ts + c[2]) * ts + c[3];
+        let x = t * t * poly([0.00166, -0.00375, 0.00209, 0.0])
+            + t * poly([-0.02903, 0.06377, -0.03202, 0.00394])
+            + poly([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * poly([0.00275, -0.00610, 0.00317, 0.0])
+            + t * poly([-0.04214, 0.08970, -0.04153, 0.00516])
+            + poly([0.15346, -0.26756, 0.06670, 0.26688]);
+        #[rustfmt::skip]
+        let coeffs = [
+            [ 0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251,  0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        Preetham {
+            sun,
+            ts,
+            zenith: [lum, x, y],
+            coeffs,
+            scale,
+        }
+    }
+
+    fn perez(&self, k: usize, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.coeffs[k];
+        let f = |cos_theta: f64, gamma: f64| {
+            let cg = gamma.cos();
+            (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cg * cg)
+        };
+        f(cos_theta, gamma) / f(1.0, self.ts)
+    }
+
+    fn radiance(&self, d: Vec3) -> Vec3 {
+        if d.y <= 0.0 {
+            return Vec3::zero();
+        }
+        let cos_theta = d.y.max(1e-3);
+        let gamma = d.dot(&self.sun).clamp(-1.0, 1.0).acos();
+        let lum = self.zenith[0] * self.perez(0, cos_theta, gamma);
+        let x = self.zenith[1] * self.perez(1, cos_theta, gamma);
+        let y = self.zenith[2] * self.perez(2, cos_theta, gamma);
+        xyy_to_rgb(x, y, lum) * self.scale
+    }
+}
+
+// Of sunlight crossing the air at zenith angle ts, at roughly the red, green
+// and blue wavelengths: Rayleigh scattering and haze (Ångström's formula)
+// over the Kasten-Young air mass.
+fn transmittance(ts: f64, turbidity: f64) -> Vec3 {
+    let m = 1.0 / (ts.cos() + 0.15 * (93.885 - ts.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let t = |lambda: f64| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let haze = beta * lambda.powf(-1.3);
+        (-m * (rayleigh + haze)).exp()
+    };
+    Vec3::new(t(0.68), t(0.55), t(0.44))
+}
+
+// CIE xyY to linear sRGB, without colours outside it
+fn xyy_to_rgb(x: f64, y: f64, lum: f64) -> Vec3 {
+    let cx = x / y * lum;
+    let cz = (1.0 - x - y) / y * lum;
+    Vec3::new(
+        (3.2406 * cx - 1.5372 * lum - 0.4986 * cz).max(0.0),
+        (-0.9689 * cx + 1.8758 * lum + 0.0415 * cz).max(0.0),
+        (0.0557 * cx - 0.2040 * lum + 1.0570 * cz).max(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sun_below_horizon() {
+        let sky = Sky::new(-10.0, 0.0, 3.0, 1.0);
+        assert_eq!(sky.p_sun, 0.0);
+        let l = sky.radiance(sky.sun);
+        assert_eq!((l.x, l.y, l.z), (0.0, 0.0, 0.0));
+    }
+
+    // a sun on the horizon is cut off halfway up its disk
+    #[test]
+    fn sun_on_horizon() {
+        let sky = Sky::new(0.0, 0.0, 3.0, 1.0);
+        let edge = |y: f64| (sky.sun + Vec3::new(0.0, y * 0.5 * SUN_RADIUS, 0.0)).norm();
+        assert!(luminance(sky.radiance(edge(1.0))) > 0.0);
+        assert_eq!(luminance(sky.radiance(edge(-1.0))), 0.0);
+    }
+}
@@ -0,0 +1,263 @@
+// Colours that vary over a surface, looked up by the hit's uv.
+
+use crate::scenefile::{resolve, vec_str};
+use crate::Vec3;
+use std::path::Path;
+
+pub struct Texture {
+    pub name: String,
+    // uv units per repeat of the pattern are 1 / scale
+    pub scale: f64,
+    pub kind: Kind,
+}
+
+pub enum Kind {
+    // absolute path of the image, for export
+    Image { file: String, img: Image },
+    // squares of a and b
+    Checker(Vec3, Vec3),
+    // fractal value noise blending from a to b
+    Noise(Vec3, Vec3),
+    // a at v = 0 to b at v = 1
+    Gradient(Vec3, Vec3),
+}
+
+impl Texture {
+    pub fn new(name: &str, scale: f64, kind: Kind) -> Texture {
+        Texture {
+            name: name.to_string(),
+            scale,
+            kind,
+        }
+    }
+
+    pub fn image(name: &str, scale: f64, file: &str, dir: &Path) -> Result<Texture, String> {
+        let file = resolve(file, dir)?;
+        let img = Image::load(&file)?;
+        Ok(Texture::new(name, scale, Kind::Image { file, img }))
+    }
+
+    pub fn value(&self, uv: (f64, f64)) -> Vec3 {
+        let (u, v) = (uv.0 * self.scale, uv.1 * self.scale);
+        match &self.kind {
+            Kind::Image { img, .. } => img.value(u, v),
+            Kind::Checker(a, b) => {
+                if (u.floor() + v.floor()).rem_euclid(2.0) == 0.0 {
+                    *a
+                } else {
+                    *b
+                }
+            }
+            Kind::Noise(a, b) => {
+                let t = fbm(u, v, period(self.scale));
+                *a * (1.0 - t) + *b * t
+            }
+            Kind::Gradient(a, b) => {
+                let t = v.clamp(0.0, 1.0);
+                *a * (1.0 - t) + *b * t
+            }
+        }
+    }
+
+    // scene file fields after the name
+    pub fn export(&self) -> String {
+        let fields = match &self.kind {
+            Kind::Image { file, .. } => format!("type=image file={}", file),
+            Kind::Checker(a, b) => format!("type=checker a={} b={}", vec_str(*a), vec_str(*b)),
+            Kind::Noise(a, b) => format!("type=noise a={} b={}", vec_str(*a), vec_str(*b)),
+            Kind::Gradient(a, b) => format!("type=gradient a={} b={}", vec_str(*a), vec_str(*b)),
+        };
+        format!("{} scale={}", fields, self.scale)
+    }
+}
+
+// Pixels in linear colour, top row first.
+pub struct Image {
+    pub w: usize,
+    pub h: usize,
+    pub px: Vec<Vec3>,
+}
+
+impl Image {
+    // Reads anything the image crate can. Radiance HDR and OpenEXR files are
+    // linear already; 8-bit images are taken to be sRGB-ish and brought to
+    // linear with the output's gamma.
+    pub fn load(path: &str) -> Result<Image, String> {
+        let img = image::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let linear = matches!(
+            img,
+            image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)
+        );
+        let img = img.to_rgb32f();
+        let decode = |x: f32| {
+            if linear {
+                x as f64
+            } else {
+                (x as f64).powf(2.2)
+            }
+        };
+        let px = img
+            .pixels()
+            .map(|p| Vec3::new(decode(p[0]), decode(p[1]), decode(p[2])))
+            .collect();
+        Ok(Image {
+            w: img.width() as usize,
+            h: img.height() as usize,
+            px,
+        })
+    }
+
+    // wraps round in u but not over the poles in v
+    pub fn at(&self, i: i64, j: i64) -> Vec3 {
+        let i = i.rem_euclid(self.w as i64) as usize;
+        let j = j.clamp(0, self.h as i64 - 1) as usize;
+        self.px[j * self.w + i]
+    }
+
+    // bilinear, repeating in u, with v = 0 at the bottom row
+    pub fn value(&self, u: f64, v: f64) -> Vec3 {
+        let x = u * self.w as f64 - 0.5;
+        let y = (1.0 - v) * self.h as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (i, j) = (x0 as i64, y0 as i64);
+        let top = self.at(i, j) * (1.0 - fx) + self.at(i + 1, j) * fx;
+        let bottom = self.at(i, j + 1) * (1.0 - fx) + self.at(i + 1, j + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+// Noise cells along u before the pattern repeats: one uv unit when scale is
+// a whole number, so that it wraps round a sphere without a seam.
+fn period(scale: f64) -> Option<i64> {
+    if scale >= 1.0 && scale.fract() == 0.0 {
+        Some(scale as i64)
+    } else {
+        None
+    }
+}
+
+// Four octaves of value noise, in [0, 1].
+fn fbm(u: f64, v: f64, period: Option<i64>) -> f64 {
+    let mut sum = 0.0;
+    let mut amp = 0.5;
+    let mut f = 1.0;
+    for octave in 0..4 {
+        sum += amp * noise(u * f, v * f, period.map(|p| p << octave));
+        amp *= 0.5;
+        f *= 2.0;
+    }
+    sum / 0.9375
+}
+
+// Random values at integer points, blended smoothly in between.
+fn noise(u: f64, v: f64, period: Option<i64>) -> f64 {
+    let (i, j) = (u.floor(), v.floor());
+    let (fu, fv) = (u - i, v - j);
+    let (i, j) = (i as i64, j as i64);
+    let at = |i: i64, j: i64| {
+        let i = period.map_or(i, |p| i.rem_euclid(p));
+        lattice(i, j)
+    };
+    let (su, sv) = (fu * fu * (3.0 - 2.0 * fu), fv * fv * (3.0 - 2.0 * fv));
+    let a = at(i, j) * (1.0 - su) + at(i + 1, j) * su;
+    let b = at(i, j + 1) * (1.0 - su) + at(i + 1, j + 1) * su;
+    a * (1.0 - sv) + b * sv
+}
+
+// The same for every render, unlike random().
+fn lattice(i: i64, j: i64) -> f64 {
+    let mut h = (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (j as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    h ^= h >> 31;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 29;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shape::{Shape, Sphere};
+    use crate::Ray;
+
+    fn grey(x: f64) -> Vec3 {
+        Vec3::new(x, x, x)
+    }
+
+    // 4 x 2, top row 0 1 2 3 and bottom row 10 11 12 13
+    fn image() -> Image {
+        Image {
+            w: 4,
+            h: 2,
+            px: [0.0, 1.0, 2.0, 3.0, 10.0, 11.0, 12.0, 13.0]
+                .into_iter()
+                .map(grey)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn image_lookup() {
+        let img = image();
+        // pixel centres, v = 0 at the bottom
+        assert_eq!(img.value(0.125, 0.75).x, 0.0);
+        assert_eq!(img.value(0.625, 0.25).x, 12.0);
+        // bilinear in between
+        assert!((img.value(0.25, 0.5).x - 5.5).abs() < 1e-12);
+        // wrapping round in u, from the last column back to the first
+        assert!((img.value(1.0, 0.75).x - 1.5).abs() < 1e-12);
+        assert!((img.value(0.0, 0.25).x - 11.5).abs() < 1e-12);
+        assert!((img.value(1.125, 0.75).x).abs() < 1e-12);
+        // but not over the poles
+        assert!((img.value(0.125, 1.0).x).abs() < 1e-12);
+        assert!((img.value(0.125, 0.0).x - 10.0).abs() < 1e-12);
+        assert!((img.value(0.125, -0.3).x - 10.0).abs() < 1e-12);
+        assert_eq!(img.at(-1, 5).x, 13.0);
+    }
+
+    #[test]
+    fn patterns() {
+        let (a, b) = (grey(0.2), grey(0.8));
+        let checker = Texture::new("c", 2.0, Kind::Checker(a, b));
+        assert_eq!(checker.value((0.1, 0.1)).x, 0.2);
+        assert_eq!(checker.value((0.6, 0.1)).x, 0.8);
+        assert_eq!(checker.value((0.6, 0.6)).x, 0.2);
+        assert_eq!(checker.value((-0.1, 0.1)).x, 0.8);
+        let gradient = Texture::new("g", 1.0, Kind::Gradient(a, b));
+        assert!((gradient.value((0.3, 0.5)).x - 0.5).abs() < 1e-12);
+        assert_eq!(gradient.value((0.3, 1.5)).x, 0.8);
+        assert_eq!(gradient.value((0.3, -1.0)).x, 0.2);
+    }
+
+    #[test]
+    fn noise() {
+        let t = Texture::new("n", 8.0, Kind::Noise(grey(0.0), grey(1.0)));
+        for k in 0..1000 {
+            let (u, v) = ((k as f64 * 0.618).fract(), (k as f64 * 0.377).fract());
+            let x = t.value((u, v)).x;
+            assert!((0.0..=1.0).contains(&x), "{}", x);
+            // the same every time, and no seam where u wraps round
+            assert_eq!(x, t.value((u, v)).x);
+            assert!((t.value((0.0, v)).x - t.value((1.0, v)).x).abs() < 1e-12);
+        }
+        // with a fractional scale it doesn't repeat
+        let t = Texture::new("n", 2.5, Kind::Noise(grey(0.0), grey(1.0)));
+        assert!((t.value((0.0, 0.3)).x - t.value((1.0, 0.3)).x).abs() > 1e-6);
+    }
+
+    // Spheres are mapped by longitude and latitude.
+    #[test]
+    fn sphere_uv() {
+        let s = Sphere::new(2.0, Vec3::new(1.0, 1.0, 1.0), 0);
+        let uv = |o: Vec3, d: Vec3| s.intersect(&Ray::new(o, d), 1e20).unwrap().uv;
+        let (_, v) = uv(Vec3::new(1.0, 10.0, 1.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((v - 1.0).abs() < 1e-12);
+        let (_, v) = uv(Vec3::new(1.0, -10.0, 1.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(v.abs() < 1e-12);
+        let (u, v) = uv(Vec3::new(10.0, 1.0, 1.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!((u - 0.5).abs() < 1e-12 && (v - 0.5).abs() < 1e-12);
+        let (u, _) = uv(Vec3::new(1.0, 1.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!((u - 0.75).abs() < 1e-12);
+    }
+}
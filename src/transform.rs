@@ -0,0 +1,138 @@
+use crate::Vec3;
+
+// Scale, then rotate about x, y and z (degrees), then translate.
+pub struct Transform {
+    pub scale: Vec3,
+    pub rotate: Vec3,
+    pub translate: Vec3,
+    // rows of the linear part and of its inverse
+    m: [Vec3; 3],
+    inv: [Vec3; 3],
+}
+
+impl Transform {
+    pub fn new(scale: Vec3, rotate: Vec3, translate: Vec3) -> Transform {
+        let s = scale;
+        let [c0, c1, c2] = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ]
+        .map(|a| rot(rotate, a));
+        Transform {
+            scale,
+            rotate,
+            translate,
+            m: [
+                Vec3::new(c0.x * s.x, c1.x * s.y, c2.x * s.z),
+                Vec3::new(c0.y * s.x, c1.y * s.y, c2.y * s.z),
+                Vec3::new(c0.z * s.x, c1.z * s.y, c2.z * s.z),
+            ],
+            inv: [c0 * (1.0 / s.x), c1 * (1.0 / s.y), c2 * (1.0 / s.z)],
+        }
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        mul(&self.m, p) + self.translate
+    }
+
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        // inverse transpose
+        (self.inv[0] * n.x + self.inv[1] * n.y + self.inv[2] * n.z).norm()
+    }
+
+    pub fn inverse_point(&self, p: Vec3) -> Vec3 {
+        mul(&self.inv, p - self.translate)
+    }
+
+    pub fn inverse_vector(&self, v: Vec3) -> Vec3 {
+        mul(&self.inv, v)
+    }
+}
+
+fn mul(m: &[Vec3; 3], v: Vec3) -> Vec3 {
+    Vec3::new(m[0].dot(&v), m[1].dot(&v), m[2].dot(&v))
+}
+
+fn rot(rotate: Vec3, v: Vec3) -> Vec3 {
+    let (sx, cx) = rotate.x.to_radians().sin_cos();
+    let (sy, cy) = rotate.y.to_radians().sin_cos();
+    let (sz, cz) = rotate.z.to_radians().sin_cos();
+    let v = Vec3::new(v.x, v.y * cx - v.z * sx, v.y * sx + v.z * cx);
+    let v = Vec3::new(v.x * cy + v.z * sy, v.y, -v.x * sy + v.z * cy);
+    Vec3::new(v.x * cz - v.y * sz, v.x * sz + v.y * cz, v.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        let d = a - b;
+        d.dot(&d).sqrt() < 1e-9
+    }
+
+    #[test]
+    fn order() {
+        // scaled first, then turned a quarter about z, then moved
+        let xf = Transform::new(
+            Vec3::new(2.0, 3.0, 4.0),
+            Vec3::new(0.0, 0.0, 90.0),
+            Vec3::new(10.0, 20.0, 30.0),
+        );
+        assert!(near(
+            xf.point(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(10.0, 22.0, 30.0)
+        ));
+        assert!(near(
+            xf.point(Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(7.0, 20.0, 30.0)
+        ));
+        assert!(near(
+            xf.point(Vec3::new(0.0, 0.0, 1.0)),
+            Vec3::new(10.0, 20.0, 34.0)
+        ));
+        // x, then y, then z
+        let xf = Transform::new(
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(90.0, 90.0, 0.0),
+            Vec3::zero(),
+        );
+        assert!(near(
+            xf.point(Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(1.0, 0.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn inverse() {
+        let xf = Transform::new(
+            Vec3::new(2.0, -0.5, 3.0),
+            Vec3::new(10.0, -40.0, 75.0),
+            Vec3::new(1.0, 2.0, 3.0),
+        );
+        for p in [Vec3::new(1.0, 2.0, 3.0), Vec3::new(-5.0, 0.5, 7.0)] {
+            assert!(near(xf.inverse_point(xf.point(p)), p));
+            let v = xf.point(p) - xf.point(Vec3::zero());
+            assert!(near(xf.inverse_vector(v), p));
+        }
+    }
+
+    #[test]
+    fn normals() {
+        // stay perpendicular to the surface under uneven scaling
+        let xf = Transform::new(
+            Vec3::new(1.0, 4.0, 0.5),
+            Vec3::new(30.0, 20.0, 10.0),
+            Vec3::new(5.0, 5.0, 5.0),
+        );
+        let n = Vec3::new(1.0, 1.0, 1.0).norm();
+        let (a, b) = (Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, -1.0));
+        let tn = xf.normal(n);
+        assert!((tn.dot(&tn) - 1.0).abs() < 1e-12);
+        for t in [a, b] {
+            let tv = xf.point(t) - xf.point(Vec3::zero());
+            assert!(tn.dot(&tv).abs() < 1e-9);
+        }
+    }
+}